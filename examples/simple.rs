@@ -1,16 +1,107 @@
 extern crate rust_prompt;
 
-use rust_prompt::{arguments, command, terminal};
+use rust_prompt::{
+    arguments, command, completion, config, highlight, keymap, prompt, terminal, theme,
+};
 
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use termion::color;
+use termion::event::Key;
+
+#[derive(Default)]
+struct AppState {
+    sessions: Vec<String>,
+}
 
 fn main() {
     let mut terminal = terminal::Terminal::new();
+    let state = Rc::new(RefCell::new(AppState::default()));
+
+    terminal.set_match_mode(completion::MatchMode::Fuzzy);
+    terminal.set_highlighter(highlight::Styles::default());
+    terminal.set_prompt(
+        prompt::Prompt::new()
+            .segment(
+                prompt::Segment::name()
+                    .style(color::Fg(color::Magenta))
+                    .around("[", "] "),
+            )
+            .segment(prompt::Segment::cwd().style(color::Fg(color::Blue)))
+            .segment(
+                prompt::Segment::git_branch()
+                    .style(color::Fg(color::Yellow))
+                    .around(" (", ")"),
+            )
+            .segment(prompt::Segment::status("", " ✘").style(color::Fg(color::Red)))
+            .segment(prompt::Segment::text(" > ")),
+    );
+    terminal.set_right_prompt(
+        prompt::Prompt::new()
+            .segment(prompt::Segment::duration().around("took ", " "))
+            .segment(prompt::Segment::time().style(color::Fg(color::LightBlack))),
+    );
+    terminal.set_context_name(Some("local"));
+    if env::args().any(|arg| arg == "--light") {
+        terminal.set_theme(theme::Theme::light());
+    }
+    if env::args().any(|arg| arg == "--vi") {
+        terminal.set_edit_mode(terminal::EditMode::Vi);
+    }
+    terminal.keymap_mut().bind_callback(&[Key::F(1)], |_| {
+        keymap::CallbackResult::Execute("help".to_string())
+    });
+    terminal
+        .keymap_mut()
+        .bind_callback(&[Key::Ctrl('x'), Key::Char('p')], |buffer| {
+            buffer.move_home();
+            buffer.insert_str("ping ");
+            keymap::CallbackResult::Continue
+        });
+    if let Some(home) = env::var_os("HOME") {
+        let path = PathBuf::from(home).join(".rust_prompt_history");
+        if let Err(e) = terminal.set_history_file(&path) {
+            eprintln!("could not load history from {}: {}", path.display(), e);
+        }
+    }
+
+    match config::Config::load("rust_prompt") {
+        Ok(config) => {
+            if let Err(e) = terminal.apply_config(&config) {
+                eprintln!("could not apply configuration: {}", e);
+            }
+        }
+        Err(e) => eprintln!("could not load configuration: {}", e),
+    }
 
     terminal.add_command("quit", quit, "quit application");
     terminal.add_command("help", help, "show help information");
     terminal.add_command("version", version, "show application version");
-    terminal.add_command("ssh", ssh, "run ssh");
+    terminal.add_command(
+        "ssh",
+        Ssh {
+            state: Rc::clone(&state),
+        },
+        "run ssh",
+    );
+    let sessions_state = Rc::clone(&state);
+    terminal.add_command(
+        "sessions",
+        move |args| sessions(&sessions_state.borrow(), args),
+        "list ssh sessions",
+    );
+    terminal.add_command("ping", command::typed(ping), "ping hosts");
+    terminal.add_command("cat", command::typed(cat), "print a file");
+    terminal.add_command_group("db", "database commands");
+    terminal.add_command_group("db migrate", "run database migrations");
+    terminal.add_command("db migrate up", db_migrate_up, "apply migrations");
+    terminal.add_command("db migrate down", db_migrate_down, "revert migrations");
+    terminal.add_command("db status", db_status, "show database status");
+    terminal.add_command("sql", Sql, "run a query, which ends with ;");
     terminal.add_command("fail", fail, "run fail");
     terminal.add_command("wtfismyip", wtfismyip, "get your IP Address");
     terminal.add_special_command('!', local_execute, "run command on local system");
@@ -30,26 +121,126 @@ fn version(_: HashMap<String, arguments::Argument>) -> command::CommandResult {
     command::CommandResult::Success("Version 0.0.1".to_string())
 }
 
-fn ssh(args: HashMap<String, arguments::Argument>) -> command::CommandResult {
-    if !args.contains_key("ip") {
-        return command::CommandResult::Failure("-ip - IP Address is Required".to_string());
+struct Ssh {
+    state: Rc<RefCell<AppState>>,
+}
+
+impl command::Command for Ssh {
+    fn flags(&self) -> Vec<arguments::Flag> {
+        vec![arguments::Flag::string("port")
+            .short("p")
+            .default("22")
+            .values(&["22", "2222"])
+            .help("Port")]
     }
-    let ip = if let arguments::Argument::String(s) = args.get("ip").unwrap() {
-        s
-    } else {
-        return command::CommandResult::Failure("-ip - IP Address is Required".to_string());
-    };
 
-    let default_port = arguments::Argument::String("22".to_string());
-    let port = if let arguments::Argument::String(s) = args.get("port").unwrap_or(&default_port) {
-        s
-    } else {
-        return command::CommandResult::Failure(
-            "-port - Port must be provided a value".to_string(),
-        );
-    };
+    fn positionals(&self) -> Vec<arguments::Positional> {
+        let state = Rc::clone(&self.state);
+        vec![arguments::Positional::new("ip")
+            .required()
+            .help("IP Address")
+            .completer(move |_| {
+                state
+                    .borrow()
+                    .sessions
+                    .iter()
+                    .filter_map(|s| s.split(':').next())
+                    .map(|s| s.to_string())
+                    .collect()
+            })]
+    }
+
+    fn execute(&mut self, args: HashMap<String, arguments::Argument>) -> command::CommandResult {
+        let (ip, port) = match (&args["ip"], &args["port"]) {
+            (arguments::Argument::String(ip), arguments::Argument::String(port)) => (ip, port),
+            _ => unreachable!("validated against flags"),
+        };
+
+        let session = format!("{}:{}", ip, port);
+        self.state.borrow_mut().sessions.push(session.clone());
+
+        command::CommandResult::Success(format!("Connecting to SSH {}", session))
+    }
+}
+
+fn sessions(state: &AppState, _: HashMap<String, arguments::Argument>) -> command::CommandResult {
+    if state.sessions.is_empty() {
+        return command::CommandResult::Success("No sessions".to_string());
+    }
+    command::CommandResult::Success(state.sessions.join(", "))
+}
+
+#[derive(arguments::PromptArgs)]
+struct PingArgs {
+    /// Host to ping
+    #[prompt(positional)]
+    host: String,
+    /// Number of packets
+    #[prompt(short = "c", default = "4")]
+    count: u32,
+    /// Additional hosts
+    #[prompt(positional)]
+    also: Vec<String>,
+    /// Interval in seconds
+    interval: Option<f32>,
+    /// Show every reply
+    #[prompt(short = "v")]
+    verbose: bool,
+}
+
+fn ping(args: PingArgs) -> command::CommandResult {
+    let mut hosts = vec![args.host];
+    hosts.extend(args.also);
+
+    command::CommandResult::Success(format!(
+        "Pinging {} {} times every {}s{}",
+        hosts.join(", "),
+        args.count,
+        args.interval.unwrap_or(1.0),
+        if args.verbose { " (verbose)" } else { "" },
+    ))
+}
+
+#[derive(arguments::PromptArgs)]
+struct CatArgs {
+    /// File to print
+    #[prompt(positional)]
+    path: PathBuf,
+}
+
+fn cat(args: CatArgs) -> command::CommandResult {
+    match fs::read_to_string(&args.path) {
+        Ok(contents) => command::CommandResult::Success(contents.trim_end().replace('\n', "\r\n")),
+        Err(e) => command::CommandResult::Failure(format!("{}: {}", args.path.display(), e)),
+    }
+}
+
+struct Sql;
+
+impl command::Command for Sql {
+    fn is_incomplete(&self, input: &str) -> bool {
+        !input.trim_end().ends_with(';')
+    }
+
+    fn execute(&mut self, args: HashMap<String, arguments::Argument>) -> command::CommandResult {
+        let query = match args.get(arguments::POSITIONAL) {
+            Some(arguments::Argument::List(words)) => words.join(" "),
+            _ => return command::CommandResult::Failure("expected a query".to_string()),
+        };
+        command::CommandResult::Success(format!("Running query: {}", query))
+    }
+}
+
+fn db_migrate_up(_: HashMap<String, arguments::Argument>) -> command::CommandResult {
+    command::CommandResult::Success("Migrations applied".to_string())
+}
+
+fn db_migrate_down(_: HashMap<String, arguments::Argument>) -> command::CommandResult {
+    command::CommandResult::Success("Migrations reverted".to_string())
+}
 
-    command::CommandResult::Success(format!("Connecting to SSH {}:{}", ip, port))
+fn db_status(_: HashMap<String, arguments::Argument>) -> command::CommandResult {
+    command::CommandResult::Success("Database is up to date".to_string())
 }
 
 fn fail(_: HashMap<String, arguments::Argument>) -> command::CommandResult {
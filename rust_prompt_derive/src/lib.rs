@@ -0,0 +1,218 @@
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Expr, ExprLit, Fields, Lit, LitStr, Meta};
+
+/// Derives `rust_prompt::arguments::PromptArgs` for a struct with named fields.
+///
+/// Each field becomes a flag named after the field, or a positional argument
+/// in declaration order when marked `#[prompt(positional)]`. Fields accept a
+/// `#[prompt(name = "..", short = "..", default = "..", help = "..")]`
+/// attribute, and doc comments are used as help text when `help` is not set.
+/// `bool` fields are flags that are false unless given, so they can't be
+/// positional or have a default.
+#[proc_macro_derive(PromptArgs, attributes(prompt))]
+pub fn derive_prompt_args(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+struct FieldOptions {
+    name: String,
+    short: Option<String>,
+    default: Option<String>,
+    help: String,
+    positional: bool,
+}
+
+fn field_options(field: &syn::Field) -> syn::Result<FieldOptions> {
+    let ident = field.ident.as_ref().unwrap();
+    let mut options = FieldOptions {
+        name: ident.to_string().trim_start_matches("r#").to_string(),
+        short: None,
+        default: None,
+        help: String::new(),
+        positional: false,
+    };
+    let mut docs = Vec::new();
+
+    for attr in field.attrs.iter() {
+        if attr.path().is_ident("doc") {
+            if let Meta::NameValue(nv) = &attr.meta {
+                if let Expr::Lit(ExprLit {
+                    lit: Lit::Str(s), ..
+                }) = &nv.value
+                {
+                    docs.push(s.value().trim().to_string());
+                }
+            }
+            continue;
+        }
+        if !attr.path().is_ident("prompt") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("positional") {
+                options.positional = true;
+                return Ok(());
+            }
+            let value = meta.value()?.parse::<LitStr>()?.value();
+            if meta.path.is_ident("name") {
+                options.name = value;
+            } else if meta.path.is_ident("short") {
+                options.short = Some(value);
+            } else if meta.path.is_ident("default") {
+                options.default = Some(value);
+            } else if meta.path.is_ident("help") {
+                options.help = value;
+            } else {
+                return Err(
+                    meta.error("expected `positional`, `name`, `short`, `default` or `help`")
+                );
+            }
+            Ok(())
+        })?;
+    }
+
+    if options.positional && options.short.is_some() {
+        return Err(syn::Error::new_spanned(
+            ident,
+            "positional arguments can't have a short alias",
+        ));
+    }
+
+    if is_bool(&field.ty) {
+        if options.positional {
+            return Err(syn::Error::new_spanned(
+                ident,
+                "`bool` fields are flags and can't be positional",
+            ));
+        }
+        if options.default.is_some() {
+            return Err(syn::Error::new_spanned(
+                ident,
+                "`bool` fields can't have a default, they are false unless the flag is given",
+            ));
+        }
+    }
+
+    if options.help.is_empty() {
+        options.help = docs.join(" ");
+    }
+
+    Ok(options)
+}
+
+fn is_bool(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Path(path) => path.qself.is_none() && path.path.is_ident("bool"),
+        _ => false,
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    ident,
+                    "PromptArgs can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                ident,
+                "PromptArgs can only be derived for structs",
+            ))
+        }
+    };
+
+    let arguments = quote!(::rust_prompt::arguments);
+    let mut flags = Vec::new();
+    let mut positionals = Vec::new();
+    let mut values = Vec::new();
+
+    for field in fields.iter() {
+        let field_ident = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+        let options = field_options(field)?;
+        let name = &options.name;
+        let help = &options.help;
+
+        let short = match &options.short {
+            Some(short) => quote!(let flag = flag.short(#short);),
+            None => quote!(),
+        };
+        let (flag_default, positional_default, value_default) = match &options.default {
+            Some(default) => (
+                quote!(let mut flag = flag.default(#default); flag.required = false;),
+                quote!(let mut positional = positional.default(#default); positional.required = false;),
+                quote!(.or(Some(&#arguments::Argument::String(#default.to_string())))),
+            ),
+            None => (quote!(), quote!(), quote!()),
+        };
+        let lookup_short = match &options.short {
+            Some(short) => quote!(.or_else(|| args.get(#short))),
+            None => quote!(),
+        };
+
+        if options.positional {
+            positionals.push(quote! {
+                {
+                    let flag = <#ty as #arguments::FromArgument>::flag(#name);
+                    let mut positional = #arguments::Positional::new(#name).help(#help);
+                    positional.required = flag.required;
+                    positional.multiple = flag.multiple;
+                    positional.completer = flag.completer;
+                    #positional_default
+                    positional
+                }
+            });
+        } else {
+            flags.push(quote! {
+                {
+                    let flag = <#ty as #arguments::FromArgument>::flag(#name);
+                    #short
+                    #flag_default
+                    flag.help(#help)
+                }
+            });
+        }
+        values.push(quote! {
+            #field_ident: <#ty as #arguments::FromArgument>::from_argument(
+                #name,
+                args.get(#name) #lookup_short #value_default,
+            )?
+        });
+    }
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics #arguments::PromptArgs for #ident #ty_generics #where_clause {
+            fn flags() -> ::std::vec::Vec<#arguments::Flag> {
+                vec![#(#flags),*]
+            }
+
+            fn positionals() -> ::std::vec::Vec<#arguments::Positional> {
+                vec![#(#positionals),*]
+            }
+
+            fn from_arguments(
+                args: &::std::collections::HashMap<::std::string::String, #arguments::Argument>,
+            ) -> ::std::result::Result<Self, #arguments::ValidationError> {
+                Ok(#ident {
+                    #(#values),*
+                })
+            }
+        }
+    })
+}
@@ -1,75 +1,938 @@
+use crate::completion::{self, Completer};
 use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::rc::Rc;
 
-#[derive(Clone)]
+pub use rust_prompt_derive::PromptArgs;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Argument {
     String(String),
     Bool,
+    /// Values of a flag given more than once.
+    List(Vec<String>),
 }
 
+/// Key under which commands without a schema receive positional arguments.
+pub const POSITIONAL: &str = "--";
+
+#[derive(Debug, PartialEq, Eq)]
 pub enum ArgumentResult {
     Special(char, String),
-    Command(String, HashMap<String, Argument>),
+    /// Command name, positional arguments in order, and flags.
+    Command(String, Vec<String>, HashMap<String, Argument>),
 }
 
-pub fn parse_arguments<S: AsRef<str>>(line: S) -> Option<ArgumentResult> {
-    let first_char: char = line.as_ref().as_bytes()[0].into();
-    let line = line.as_ref().to_string();
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ArgumentType {
+    String,
+    Bool,
+}
 
-    if (first_char > ' ' && first_char < '0') || (first_char > '9' && first_char < 'A') {
-        return Some(ArgumentResult::Special(first_char, line[1..].to_string()));
+/// Declaration of a flag accepted by a command.
+#[derive(Clone)]
+pub struct Flag {
+    pub name: String,
+    pub short: Option<String>,
+    pub kind: ArgumentType,
+    pub required: bool,
+    pub default: Option<String>,
+    pub multiple: bool,
+    pub help: String,
+    pub completer: Option<Completer>,
+}
+
+impl Flag {
+    pub fn new<S: AsRef<str>>(name: S, kind: ArgumentType) -> Flag {
+        Flag {
+            name: name.as_ref().to_string(),
+            short: None,
+            kind,
+            required: false,
+            default: None,
+            multiple: false,
+            help: "".to_string(),
+            completer: None,
+        }
+    }
+
+    pub fn string<S: AsRef<str>>(name: S) -> Flag {
+        Flag::new(name, ArgumentType::String)
     }
 
-    let mut line_elements = line.split_ascii_whitespace();
-    let cmd = line_elements.next()?;
-    let line_elements: Vec<&str> = line_elements.collect();
+    pub fn bool<S: AsRef<str>>(name: S) -> Flag {
+        Flag::new(name, ArgumentType::Bool)
+    }
 
-    let mut args = HashMap::<String, Argument>::new();
+    pub fn short<S: AsRef<str>>(mut self, short: S) -> Flag {
+        self.short = Some(short.as_ref().to_string());
+        self
+    }
+
+    pub fn required(mut self) -> Flag {
+        self.required = true;
+        self
+    }
+
+    /// Value used when the flag is not given. Bool flags are set by a
+    /// default of `true` and left unset by any other.
+    pub fn default<S: AsRef<str>>(mut self, default: S) -> Flag {
+        self.default = Some(default.as_ref().to_string());
+        self
+    }
+
+    /// Allows the flag to be given more than once.
+    pub fn multiple(mut self) -> Flag {
+        self.multiple = true;
+        self
+    }
+
+    pub fn help<S: AsRef<str>>(mut self, help: S) -> Flag {
+        self.help = help.as_ref().to_string();
+        self
+    }
+
+    /// Sets how values of the flag are completed.
+    pub fn completer<F: Fn(&str) -> Vec<String> + 'static>(mut self, completer: F) -> Flag {
+        self.completer = Some(Rc::new(completer));
+        self
+    }
+
+    /// Completes values of the flag from a fixed list.
+    pub fn values<S: AsRef<str>>(mut self, values: &[S]) -> Flag {
+        self.completer = Some(completion::values(values));
+        self
+    }
 
-    let element_count = line_elements.len();
-    if element_count == 0 {
-        return Some(ArgumentResult::Command(cmd.to_string(), args));
+    /// Declares the flag's value as a path, completed from the filesystem.
+    pub fn path(mut self) -> Flag {
+        self.completer = Some(completion::paths());
+        self
     }
 
-    let mut index = 0;
-    loop {
-        if !line_elements[index].to_string().starts_with('-') {
-            return None;
+    fn matches(&self, key: &str) -> bool {
+        self.name == key || self.short.as_deref() == Some(key)
+    }
+}
+
+/// Declaration of a positional argument accepted by a command.
+#[derive(Clone)]
+pub struct Positional {
+    pub name: String,
+    pub required: bool,
+    pub default: Option<String>,
+    /// Collects all remaining positional arguments, only valid last.
+    pub multiple: bool,
+    pub help: String,
+    pub completer: Option<Completer>,
+}
+
+impl Positional {
+    pub fn new<S: AsRef<str>>(name: S) -> Positional {
+        Positional {
+            name: name.as_ref().to_string(),
+            required: false,
+            default: None,
+            multiple: false,
+            help: "".to_string(),
+            completer: None,
         }
-        let key = line_elements[index]
-            .to_string()
-            .trim_start_matches('-')
-            .to_string();
+    }
 
-        index += 1;
-        if index == element_count {
-            args.insert(key, Argument::Bool);
-            break;
+    pub fn required(mut self) -> Positional {
+        self.required = true;
+        self
+    }
+
+    pub fn default<S: AsRef<str>>(mut self, default: S) -> Positional {
+        self.default = Some(default.as_ref().to_string());
+        self
+    }
+
+    pub fn multiple(mut self) -> Positional {
+        self.multiple = true;
+        self
+    }
+
+    pub fn help<S: AsRef<str>>(mut self, help: S) -> Positional {
+        self.help = help.as_ref().to_string();
+        self
+    }
+
+    /// Sets how the argument is completed.
+    pub fn completer<F: Fn(&str) -> Vec<String> + 'static>(mut self, completer: F) -> Positional {
+        self.completer = Some(Rc::new(completer));
+        self
+    }
+
+    /// Completes the argument from a fixed list.
+    pub fn values<S: AsRef<str>>(mut self, values: &[S]) -> Positional {
+        self.completer = Some(completion::values(values));
+        self
+    }
+
+    /// Declares the argument as a path, completed from the filesystem.
+    pub fn path(mut self) -> Positional {
+        self.completer = Some(completion::paths());
+        self
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    UnknownFlag(String),
+    MissingValue(String),
+    UnexpectedValue(String),
+    MissingRequired(String, String),
+    Repeated(String),
+    InvalidValue(String, String),
+    MissingPositional(String, String),
+    UnexpectedPositional(String),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationError::UnknownFlag(name) => write!(f, "-{} - unknown flag", name),
+            ValidationError::MissingValue(name) => {
+                write!(f, "-{} - must be provided a value", name)
+            }
+            ValidationError::UnexpectedValue(name) => {
+                write!(f, "-{} - does not take a value", name)
+            }
+            ValidationError::MissingRequired(name, help) if help.is_empty() => {
+                write!(f, "-{} - is required", name)
+            }
+            ValidationError::MissingRequired(name, help) => {
+                write!(f, "-{} - {} is required", name, help)
+            }
+            ValidationError::Repeated(name) => write!(f, "-{} - can only be given once", name),
+            ValidationError::InvalidValue(name, value) => {
+                write!(f, "-{} - invalid value '{}'", name, value)
+            }
+            ValidationError::MissingPositional(name, help) if help.is_empty() => {
+                write!(f, "<{}> - is required", name)
+            }
+            ValidationError::MissingPositional(name, help) => {
+                write!(f, "<{}> - {} is required", name, help)
+            }
+            ValidationError::UnexpectedPositional(value) => {
+                write!(f, "unexpected argument '{}'", value)
+            }
         }
+    }
+}
 
-        let value = line_elements[index].to_string();
+/// Checks parsed arguments against the declared flags and positionals,
+/// resolving short aliases to flag names, binding positionals to their names
+/// and filling in defaults.
+pub fn validate_arguments(
+    flags: &[Flag],
+    positionals: &[Positional],
+    values: Vec<String>,
+    args: HashMap<String, Argument>,
+) -> Result<HashMap<String, Argument>, ValidationError> {
+    let mut validated = HashMap::<String, Argument>::new();
 
-        if value.starts_with('-') {
-            args.insert(key, Argument::Bool);
+    for (key, value) in args.into_iter() {
+        let flag = flags
+            .iter()
+            .find(|f| f.matches(&key))
+            .ok_or_else(|| ValidationError::UnknownFlag(key.clone()))?;
+
+        match (flag.kind, value) {
+            (ArgumentType::String, Argument::Bool) => {
+                return Err(ValidationError::MissingValue(flag.name.clone()))
+            }
+            (ArgumentType::Bool, Argument::String(_)) | (ArgumentType::Bool, Argument::List(_)) => {
+                return Err(ValidationError::UnexpectedValue(flag.name.clone()))
+            }
+            (ArgumentType::String, Argument::List(_)) if !flag.multiple => {
+                return Err(ValidationError::Repeated(flag.name.clone()))
+            }
+            (ArgumentType::Bool, value) => {
+                validated.insert(flag.name.clone(), value);
+            }
+            // The flag was also given under its other name, like `-p 1`
+            // with `--port 2`.
+            (ArgumentType::String, value) => match validated.remove(&flag.name) {
+                None => {
+                    validated.insert(flag.name.clone(), value);
+                }
+                Some(_) if !flag.multiple => {
+                    return Err(ValidationError::Repeated(flag.name.clone()))
+                }
+                Some(previous) => {
+                    let mut values = into_values(previous);
+                    values.extend(into_values(value));
+                    validated.insert(flag.name.clone(), Argument::List(values));
+                }
+            },
+        }
+    }
+
+    let mut values = values.into_iter();
+    for positional in positionals.iter() {
+        let value = if positional.multiple {
+            let rest: Vec<String> = values.by_ref().collect();
+            if rest.is_empty() {
+                None
+            } else {
+                Some(Argument::List(rest))
+            }
         } else {
-            args.insert(key, Argument::String(value));
-            index += 1;
+            values.next().map(Argument::String)
+        };
+
+        match (value, &positional.default) {
+            (Some(value), _) => {
+                validated.insert(positional.name.clone(), value);
+            }
+            (None, Some(default)) => {
+                validated.insert(positional.name.clone(), Argument::String(default.clone()));
+            }
+            (None, None) if positional.required => {
+                return Err(ValidationError::MissingPositional(
+                    positional.name.clone(),
+                    positional.help.clone(),
+                ));
+            }
+            (None, None) => {}
+        }
+    }
+    if let Some(value) = values.next() {
+        return Err(ValidationError::UnexpectedPositional(value));
+    }
+
+    for flag in flags.iter() {
+        if validated.contains_key(&flag.name) {
+            continue;
+        }
+        if let Some(default) = &flag.default {
+            match flag.kind {
+                ArgumentType::String => {
+                    validated.insert(flag.name.clone(), Argument::String(default.clone()));
+                }
+                ArgumentType::Bool if default == "true" => {
+                    validated.insert(flag.name.clone(), Argument::Bool);
+                }
+                ArgumentType::Bool => {}
+            }
+        } else if flag.required {
+            return Err(ValidationError::MissingRequired(
+                flag.name.clone(),
+                flag.help.clone(),
+            ));
+        }
+    }
+
+    Ok(validated)
+}
+
+fn into_values(argument: Argument) -> Vec<String> {
+    match argument {
+        Argument::String(value) => vec![value],
+        Argument::List(values) => values,
+        Argument::Bool => Vec::new(),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    Empty,
+    /// A quote, and the byte offset it was opened at, that was never closed.
+    UnterminatedQuote(char, usize),
+    TrailingEscape,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "empty input"),
+            ParseError::UnterminatedQuote(quote, position) => write!(
+                f,
+                "unterminated {} quote starting at column {}",
+                quote,
+                position + 1
+            ),
+            ParseError::TrailingEscape => write!(f, "trailing backslash"),
+        }
+    }
+}
+
+/// A word of the input line once quotes and escapes have been resolved.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token {
+    pub text: String,
+    /// Byte range of the token in the original line, quotes included.
+    pub start: usize,
+    pub end: usize,
+    pub quoted: bool,
+}
+
+impl Token {
+    pub fn is_flag(&self) -> bool {
+        !self.quoted && self.text.starts_with('-') && self.text != "-"
+    }
+
+    fn is_end_of_flags(&self) -> bool {
+        !self.quoted && self.text == "--"
+    }
+}
+
+/// Splits a line into words following POSIX shell quoting rules: single
+/// quotes are literal, double quotes allow `\"` and `\\`, and a backslash
+/// outside quotes escapes any character. A backslash before a newline, outside
+/// single quotes, joins the two lines.
+pub fn tokenize<S: AsRef<str>>(line: S) -> Result<Vec<Token>, ParseError> {
+    let line = line.as_ref();
+    let mut tokens = Vec::new();
+    let mut current: Option<Token> = None;
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((index, c)) = chars.next() {
+        if c == '\\' && matches!(chars.peek(), Some(&(_, '\n'))) {
+            chars.next();
+            continue;
+        }
+
+        if c.is_whitespace() {
+            if let Some(mut token) = current.take() {
+                token.end = index;
+                tokens.push(token);
+            }
+            continue;
         }
 
-        if index > element_count - 1 {
+        let token = current.get_or_insert_with(|| Token {
+            text: String::new(),
+            start: index,
+            end: index,
+            quoted: false,
+        });
+
+        match c {
+            '\\' => match chars.next() {
+                Some((_, escaped)) => token.text.push(escaped),
+                None => return Err(ParseError::TrailingEscape),
+            },
+            '\'' | '"' => {
+                token.quoted = true;
+                loop {
+                    match chars.next() {
+                        Some((_, q)) if q == c => break,
+                        Some((_, '\\')) if c == '"' => match chars.peek() {
+                            Some(&(_, escaped)) if escaped == '"' || escaped == '\\' => {
+                                token.text.push(escaped);
+                                chars.next();
+                            }
+                            Some(&(_, '\n')) => {
+                                chars.next();
+                            }
+                            _ => token.text.push('\\'),
+                        },
+                        Some((_, inner)) => token.text.push(inner),
+                        None => return Err(ParseError::UnterminatedQuote(c, index)),
+                    }
+                }
+            }
+            c => token.text.push(c),
+        }
+    }
+
+    if let Some(mut token) = current.take() {
+        token.end = line.len();
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+/// Escapes a word so that `tokenize` reads it back unchanged.
+pub fn escape<S: AsRef<str>>(word: S) -> String {
+    let mut escaped = String::new();
+    for c in word.as_ref().chars() {
+        if c.is_whitespace() || c == '\\' || c == '"' || c == '\'' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+pub fn parse_arguments<S: AsRef<str>>(line: S) -> Result<ArgumentResult, ParseError> {
+    parse_arguments_with_flags(line, &[])
+}
+
+/// Parses a line like `parse_arguments`, except that the word following a
+/// boolean flag in `flags` is kept as a positional instead of its value, and
+/// the word following another flag in `flags` is its value even when it
+/// starts with a dash.
+pub fn parse_arguments_with_flags<S: AsRef<str>>(
+    line: S,
+    flags: &[Flag],
+) -> Result<ArgumentResult, ParseError> {
+    let line = line.as_ref();
+    let first_char = line.chars().next().ok_or(ParseError::Empty)?;
+
+    if first_char != '"'
+        && first_char != '\''
+        && ((first_char > ' ' && first_char < '0') || (first_char > '9' && first_char < 'A'))
+    {
+        return Ok(ArgumentResult::Special(
+            first_char,
+            line[first_char.len_utf8()..].to_string(),
+        ));
+    }
+
+    let mut tokens = tokenize(line)?.into_iter().peekable();
+    let cmd = tokens.next().ok_or(ParseError::Empty)?.text;
+
+    let mut positional = Vec::<String>::new();
+    let mut args = HashMap::<String, Argument>::new();
+
+    while let Some(token) = tokens.next() {
+        if token.is_end_of_flags() {
+            positional.extend(tokens.by_ref().map(|t| t.text));
             break;
         }
+        if !token.is_flag() {
+            positional.push(token.text);
+            continue;
+        }
+        let key = token.text.trim_start_matches('-').to_string();
+        let declared = flags.iter().find(|f| f.matches(&key)).map(|f| f.kind);
+        // A flag declared to take a value takes the next word even when it
+        // starts with a dash, like `-n -5`. Undeclared flags only take words
+        // that don't look like flags.
+        let takes_value = |value: &Token| match declared {
+            Some(ArgumentType::Bool) => false,
+            Some(ArgumentType::String) => !value.is_end_of_flags(),
+            None => !value.is_flag() && !value.is_end_of_flags(),
+        };
+
+        match tokens.peek() {
+            Some(value) if takes_value(value) => {
+                let value = tokens.next().unwrap().text;
+                insert_value(&mut args, key, value);
+            }
+            _ => {
+                args.insert(key, Argument::Bool);
+            }
+        }
+    }
+
+    Ok(ArgumentResult::Command(cmd, positional, args))
+}
+
+fn insert_value(args: &mut HashMap<String, Argument>, key: String, value: String) {
+    let argument = match args.remove(&key) {
+        Some(Argument::String(previous)) => Argument::List(vec![previous, value]),
+        Some(Argument::List(mut values)) => {
+            values.push(value);
+            Argument::List(values)
+        }
+        _ => Argument::String(value),
+    };
+    args.insert(key, argument);
+}
+
+/// Conversion of parsed arguments into a typed struct, usually implemented
+/// with `#[derive(PromptArgs)]`.
+pub trait PromptArgs: Sized {
+    fn flags() -> Vec<Flag>;
+
+    fn positionals() -> Vec<Positional>;
+
+    fn from_arguments(args: &HashMap<String, Argument>) -> Result<Self, ValidationError>;
+}
+
+/// A type that can be read from a single flag.
+pub trait FromArgument: Sized {
+    fn flag(name: &str) -> Flag;
+
+    fn from_argument(name: &str, arg: Option<&Argument>) -> Result<Self, ValidationError>;
+}
+
+/// A type that can be parsed from a single flag value.
+pub trait FromValue: Sized {
+    fn from_value(name: &str, value: &str) -> Result<Self, ValidationError>;
+
+    fn completer() -> Option<Completer> {
+        None
     }
+}
 
-    // for (k, v) in args.clone().into_iter() {
-    //     match v {
-    //         Argument::String(s) => {
-    //             println!("K: {}, V: {}", k, s);
-    //         }
-    //         Argument::Bool => {
-    //             println!("K: {} Bool", k);
-    //         }
-    //     }
-    // }
+macro_rules! from_value_via_from_str {
+    ($($t:ty),*) => {
+        $(
+            impl FromValue for $t {
+                fn from_value(name: &str, value: &str) -> Result<Self, ValidationError> {
+                    value
+                        .parse()
+                        .map_err(|_| ValidationError::InvalidValue(name.to_string(), value.to_string()))
+                }
+            }
+        )*
+    };
+}
 
-    Some(ArgumentResult::Command(cmd.to_string(), args))
+from_value_via_from_str!(
+    String, char, i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64, IpAddr
+);
+
+impl FromValue for PathBuf {
+    /// Paths starting with `~` are read from the home directory, like the
+    /// completions of `completion::paths` suggest.
+    fn from_value(_: &str, value: &str) -> Result<Self, ValidationError> {
+        Ok(completion::expand_home(value))
+    }
+
+    fn completer() -> Option<Completer> {
+        Some(completion::paths())
+    }
+}
+
+impl<T: FromValue> FromArgument for T {
+    fn flag(name: &str) -> Flag {
+        let mut flag = Flag::string(name).required();
+        flag.completer = T::completer();
+        flag
+    }
+
+    fn from_argument(name: &str, arg: Option<&Argument>) -> Result<Self, ValidationError> {
+        match arg {
+            Some(Argument::String(value)) => T::from_value(name, value),
+            Some(Argument::Bool) => Err(ValidationError::MissingValue(name.to_string())),
+            Some(Argument::List(_)) => Err(ValidationError::Repeated(name.to_string())),
+            None => Err(ValidationError::MissingRequired(
+                name.to_string(),
+                "".to_string(),
+            )),
+        }
+    }
+}
+
+impl<T: FromValue> FromArgument for Option<T> {
+    fn flag(name: &str) -> Flag {
+        let mut flag = Flag::string(name);
+        flag.completer = T::completer();
+        flag
+    }
+
+    fn from_argument(name: &str, arg: Option<&Argument>) -> Result<Self, ValidationError> {
+        match arg {
+            None => Ok(None),
+            arg => T::from_argument(name, arg).map(Some),
+        }
+    }
+}
+
+impl<T: FromValue> FromArgument for Vec<T> {
+    fn flag(name: &str) -> Flag {
+        let mut flag = Flag::string(name).multiple();
+        flag.completer = T::completer();
+        flag
+    }
+
+    fn from_argument(name: &str, arg: Option<&Argument>) -> Result<Self, ValidationError> {
+        match arg {
+            None => Ok(Vec::new()),
+            Some(Argument::String(value)) => Ok(vec![T::from_value(name, value)?]),
+            Some(Argument::List(values)) => values.iter().map(|v| T::from_value(name, v)).collect(),
+            Some(Argument::Bool) => Err(ValidationError::MissingValue(name.to_string())),
+        }
+    }
+}
+
+impl FromArgument for bool {
+    fn flag(name: &str) -> Flag {
+        Flag::bool(name)
+    }
+
+    fn from_argument(name: &str, arg: Option<&Argument>) -> Result<Self, ValidationError> {
+        match arg {
+            None => Ok(false),
+            Some(Argument::Bool) => Ok(true),
+            Some(_) => Err(ValidationError::UnexpectedValue(name.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str) -> Vec<String> {
+        tokenize(line)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.text)
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_quotes_and_escapes() {
+        assert_eq!(
+            words(r#"a "b c" 'd\e' f\ g"#),
+            vec!["a", "b c", r"d\e", "f g"]
+        );
+        assert_eq!(words(r#""say \"hi\"" x"y"z"#), vec![r#"say "hi""#, "xyz"]);
+        assert_eq!(words("one \\\ntwo"), vec!["one", "two"]);
+
+        let tokens = tokenize(r#"cmd "a b""#).unwrap();
+        assert_eq!((tokens[1].start, tokens[1].end), (4, 9));
+        assert!(tokens[1].quoted);
+    }
+
+    #[test]
+    fn tokenize_errors() {
+        assert_eq!(
+            tokenize("echo 'it"),
+            Err(ParseError::UnterminatedQuote('\'', 5))
+        );
+        assert_eq!(
+            tokenize("a \"b c"),
+            Err(ParseError::UnterminatedQuote('"', 2))
+        );
+        assert_eq!(tokenize("a b\\"), Err(ParseError::TrailingEscape));
+    }
+
+    #[test]
+    fn escape_round_trips() {
+        for word in &[
+            "plain",
+            "two words",
+            r#"quo"te's"#,
+            r"back\slash",
+            "tab\there",
+            "",
+        ] {
+            let line = format!("cmd {}", escape(word));
+            let parsed = words(&line);
+            let expected: &[&str] = if word.is_empty() {
+                &["cmd"]
+            } else {
+                &["cmd", word]
+            };
+            assert_eq!(parsed, expected);
+        }
+    }
+
+    #[test]
+    fn parse_arguments_splits_flags_and_positionals() {
+        let parsed = parse_arguments("ping -c 4 host -v --interval 2 -c 5").unwrap();
+        let mut args = HashMap::new();
+        args.insert(
+            "c".to_string(),
+            Argument::List(vec!["4".to_string(), "5".to_string()]),
+        );
+        args.insert("interval".to_string(), Argument::String("2".to_string()));
+        args.insert("v".to_string(), Argument::Bool);
+        assert_eq!(
+            parsed,
+            ArgumentResult::Command("ping".to_string(), vec!["host".to_string()], args)
+        );
+    }
+
+    #[test]
+    fn parse_arguments_with_flags_keeps_words_after_bool_flags() {
+        let flags = [Flag::bool("verbose").short("v"), Flag::string("count")];
+        let parsed = parse_arguments_with_flags("ping -v host --count 3", &flags).unwrap();
+        let mut args = HashMap::new();
+        args.insert("v".to_string(), Argument::Bool);
+        args.insert("count".to_string(), Argument::String("3".to_string()));
+        assert_eq!(
+            parsed,
+            ArgumentResult::Command("ping".to_string(), vec!["host".to_string()], args)
+        );
+    }
+
+    #[test]
+    fn declared_flags_take_values_starting_with_a_dash() {
+        let flags = [Flag::string("offset").short("n"), Flag::bool("all")];
+        let parsed = parse_arguments_with_flags("seek -n -5 --all -x -1", &flags).unwrap();
+        let mut args = HashMap::new();
+        args.insert("n".to_string(), Argument::String("-5".to_string()));
+        args.insert("all".to_string(), Argument::Bool);
+        args.insert("x".to_string(), Argument::Bool);
+        args.insert("1".to_string(), Argument::Bool);
+        assert_eq!(
+            parsed,
+            ArgumentResult::Command("seek".to_string(), Vec::new(), args)
+        );
+
+        let parsed = parse_arguments_with_flags("seek --offset -- -5", &flags).unwrap();
+        let mut args = HashMap::new();
+        args.insert("offset".to_string(), Argument::Bool);
+        assert_eq!(
+            parsed,
+            ArgumentResult::Command("seek".to_string(), vec!["-5".to_string()], args)
+        );
+    }
+
+    #[test]
+    fn parse_arguments_stops_at_double_dash() {
+        let parsed = parse_arguments("rm -f -- -x '--' y").unwrap();
+        let mut args = HashMap::new();
+        args.insert("f".to_string(), Argument::Bool);
+        let positional = vec!["-x".to_string(), "--".to_string(), "y".to_string()];
+        assert_eq!(
+            parsed,
+            ArgumentResult::Command("rm".to_string(), positional, args)
+        );
+    }
+
+    #[test]
+    fn parse_arguments_special_and_empty() {
+        assert_eq!(
+            parse_arguments("!ls -la").unwrap(),
+            ArgumentResult::Special('!', "ls -la".to_string())
+        );
+        assert_eq!(parse_arguments(""), Err(ParseError::Empty));
+        assert_eq!(parse_arguments("   "), Err(ParseError::Empty));
+    }
+
+    fn validate(
+        flags: &[Flag],
+        positionals: &[Positional],
+        line: &str,
+    ) -> Result<HashMap<String, Argument>, ValidationError> {
+        match parse_arguments_with_flags(line, flags).unwrap() {
+            ArgumentResult::Command(_, values, args) => {
+                validate_arguments(flags, positionals, values, args)
+            }
+            ArgumentResult::Special(..) => panic!("not a command: {}", line),
+        }
+    }
+
+    fn string(value: &str) -> Argument {
+        Argument::String(value.to_string())
+    }
+
+    fn list(values: &[&str]) -> Argument {
+        Argument::List(values.iter().map(|v| v.to_string()).collect())
+    }
+
+    #[test]
+    fn validate_resolves_short_names_and_defaults() {
+        let flags = [
+            Flag::string("port").short("p").default("22"),
+            Flag::string("user").default("root"),
+            Flag::bool("verbose").short("v"),
+            Flag::bool("color").default("true"),
+            Flag::bool("quiet").default("false"),
+        ];
+        let args = validate(&flags, &[], "ssh -p 2222 -v").unwrap();
+        assert_eq!(args.get("port"), Some(&string("2222")));
+        assert_eq!(args.get("user"), Some(&string("root")));
+        assert_eq!(args.get("verbose"), Some(&Argument::Bool));
+        assert_eq!(args.get("color"), Some(&Argument::Bool));
+        assert_eq!(args.get("quiet"), None);
+        assert_eq!(args.len(), 4);
+    }
+
+    #[test]
+    fn validate_rejects_misused_flags() {
+        let flags = [
+            Flag::string("port").short("p"),
+            Flag::bool("verbose"),
+            Flag::string("host").required().help("Host"),
+        ];
+        let validate = |line| validate(&flags, &[], line);
+        assert_eq!(
+            validate("ssh --host h --nope"),
+            Err(ValidationError::UnknownFlag("nope".to_string()))
+        );
+        assert_eq!(
+            validate("ssh --host h -p"),
+            Err(ValidationError::MissingValue("port".to_string()))
+        );
+        assert_eq!(
+            validate_arguments(
+                &flags,
+                &[],
+                Vec::new(),
+                vec![("verbose".to_string(), string("x"))]
+                    .into_iter()
+                    .collect()
+            ),
+            Err(ValidationError::UnexpectedValue("verbose".to_string()))
+        );
+        assert_eq!(
+            validate("ssh"),
+            Err(ValidationError::MissingRequired(
+                "host".to_string(),
+                "Host".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn validate_rejects_repeated_flags() {
+        let flags = [
+            Flag::string("port").short("p"),
+            Flag::bool("verbose").short("v"),
+        ];
+        let repeated = Err(ValidationError::Repeated("port".to_string()));
+        assert_eq!(validate(&flags, &[], "ssh -p 1 -p 2"), repeated);
+        assert_eq!(validate(&flags, &[], "ssh -p 1 --port 2"), repeated);
+
+        let args = validate(&flags, &[], "ssh -v --verbose").unwrap();
+        assert_eq!(args.get("verbose"), Some(&Argument::Bool));
+    }
+
+    #[test]
+    fn validate_merges_multiple_flags_given_under_both_names() {
+        let flags = [Flag::string("include").short("I").multiple()];
+        let args = validate(&flags, &[], "cc -I a").unwrap();
+        assert_eq!(args.get("include"), Some(&string("a")));
+
+        let args = validate(&flags, &[], "cc -I a --include b -I c").unwrap();
+        let mut values = match args.get("include") {
+            Some(Argument::List(values)) => values.clone(),
+            other => panic!("expected a list, got {:?}", other),
+        };
+        values.sort();
+        assert_eq!(values, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn validate_binds_positionals() {
+        let positionals = [
+            Positional::new("source").required().help("File to copy"),
+            Positional::new("target").default("."),
+        ];
+        let args = validate(&[], &positionals, "cp a b").unwrap();
+        assert_eq!(args.get("source"), Some(&string("a")));
+        assert_eq!(args.get("target"), Some(&string("b")));
+
+        let args = validate(&[], &positionals, "cp a").unwrap();
+        assert_eq!(args.get("target"), Some(&string(".")));
+
+        assert_eq!(
+            validate(&[], &positionals, "cp"),
+            Err(ValidationError::MissingPositional(
+                "source".to_string(),
+                "File to copy".to_string()
+            ))
+        );
+        assert_eq!(
+            validate(&[], &positionals, "cp a b c"),
+            Err(ValidationError::UnexpectedPositional("c".to_string()))
+        );
+    }
+
+    #[test]
+    fn validate_collects_trailing_positionals() {
+        let positionals = [Positional::new("first"), Positional::new("rest").multiple()];
+        let args = validate(&[], &positionals, "cat a b c").unwrap();
+        assert_eq!(args.get("first"), Some(&string("a")));
+        assert_eq!(args.get("rest"), Some(&list(&["b", "c"])));
+
+        let args = validate(&[], &positionals, "cat a").unwrap();
+        assert_eq!(args.get("rest"), None);
+    }
 }
@@ -0,0 +1,824 @@
+use std::borrow::Cow;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// The text of a string without its ANSI escape sequences, like the colors
+/// written by `termion::color`, which take no space on the terminal.
+pub fn strip_ansi(string: &str) -> Cow<'_, str> {
+    if !string.contains('\x1B') {
+        return Cow::Borrowed(string);
+    }
+    let mut stripped = String::with_capacity(string.len());
+    let mut chars = string.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1B' {
+            stripped.push(c);
+            continue;
+        }
+        match chars.next() {
+            // Control sequences end with a byte from `@` to `~`.
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // Operating system commands, like window titles, end with BEL or
+            // ESC \.
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1B' {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Cow::Owned(stripped)
+}
+
+/// Number of terminal columns taken by a string. Each grapheme cluster is
+/// counted as a whole, so combining marks take no space of their own and
+/// emoji sequences take at most two columns. ANSI escape sequences take no
+/// space.
+pub fn width<S: AsRef<str>>(string: S) -> usize {
+    strip_ansi(string.as_ref())
+        .graphemes(true)
+        .map(|g| g.width().min(2))
+        .sum()
+}
+
+/// The longest start of `string` that fits in `columns` terminal columns.
+pub fn truncate(string: &str, columns: usize) -> &str {
+    let mut used = 0;
+    for (index, grapheme) in string.grapheme_indices(true) {
+        used += width(grapheme);
+        if used > columns {
+            return &string[..index];
+        }
+    }
+    string
+}
+
+/// Whether a grapheme is part of a word for word motions, which stop at
+/// anything that is not a letter or digit.
+fn is_word(grapheme: &str) -> bool {
+    grapheme.chars().any(char::is_alphanumeric)
+}
+
+fn is_blank(grapheme: &str) -> bool {
+    grapheme.chars().all(char::is_whitespace)
+}
+
+/// How `LineBuffer::change_case` changes a word.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Case {
+    Upper,
+    Lower,
+    /// Uppercases the first letter and lowercases the rest.
+    Capitalize,
+}
+
+/// Position, as a row and column, reached by writing `text` from `position`
+/// on a terminal `columns` wide. Lines wrap the way terminals wrap them: a
+/// grapheme that does not fit at the end of a row moves to the next one, and
+/// a row that is exactly filled leaves the column at `columns` until more is
+/// written. ANSI escape sequences do not move the cursor.
+pub fn advance<S: AsRef<str>>(position: (usize, usize), text: S, columns: usize) -> (usize, usize) {
+    let (mut row, mut column) = position;
+    for grapheme in strip_ansi(text.as_ref()).graphemes(true) {
+        if grapheme == "\n" || grapheme == "\r\n" {
+            row += 1;
+            column = 0;
+            continue;
+        }
+        let grapheme_width = width(grapheme);
+        if column + grapheme_width > columns {
+            row += 1;
+            column = 0;
+        }
+        column += grapheme_width;
+    }
+    (row, column)
+}
+
+/// The input being edited, which may span several lines separated by
+/// `\n`. The cursor is a byte offset that always sits on a
+/// grapheme cluster boundary, so every edit works on whole user-perceived
+/// characters.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LineBuffer {
+    text: String,
+    cursor: usize,
+}
+
+impl LineBuffer {
+    pub fn new() -> LineBuffer {
+        LineBuffer::default()
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Moves the cursor to byte offset `offset`, or to the start of the
+    /// grapheme cluster the offset falls in.
+    pub fn set_cursor(&mut self, offset: usize) {
+        if offset >= self.text.len() {
+            self.cursor = self.text.len();
+            return;
+        }
+        self.cursor = self
+            .text
+            .grapheme_indices(true)
+            .map(|(i, _)| i)
+            .take_while(|&i| i <= offset)
+            .last()
+            .unwrap_or(0);
+    }
+
+    pub fn before_cursor(&self) -> &str {
+        &self.text[..self.cursor]
+    }
+
+    pub fn after_cursor(&self) -> &str {
+        &self.text[self.cursor..]
+    }
+
+    /// Replaces the whole line, leaving the cursor at its end.
+    pub fn set<S: AsRef<str>>(&mut self, text: S) {
+        self.text = text.as_ref().to_string();
+        self.cursor = self.text.len();
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    pub fn insert_str(&mut self, string: &str) {
+        self.text.insert_str(self.cursor, string);
+        self.cursor += string.len();
+    }
+
+    /// Replaces the text from byte offset `start` up to the cursor.
+    pub fn replace_before_cursor(&mut self, start: usize, with: &str) {
+        self.text.replace_range(start..self.cursor, with);
+        self.cursor = start + with.len();
+    }
+
+    fn previous_boundary(&self) -> Option<usize> {
+        self.before_cursor()
+            .grapheme_indices(true)
+            .next_back()
+            .map(|(i, _)| i)
+    }
+
+    fn next_boundary(&self) -> Option<usize> {
+        self.after_cursor()
+            .graphemes(true)
+            .next()
+            .map(|g| self.cursor + g.len())
+    }
+
+    /// Removes the grapheme before the cursor.
+    pub fn backspace(&mut self) -> bool {
+        match self.previous_boundary() {
+            Some(start) => {
+                self.text.replace_range(start..self.cursor, "");
+                self.cursor = start;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the grapheme under the cursor.
+    pub fn delete(&mut self) -> bool {
+        match self.next_boundary() {
+            Some(end) => {
+                self.text.replace_range(self.cursor..end, "");
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn move_left(&mut self) -> bool {
+        match self.previous_boundary() {
+            Some(start) => {
+                self.cursor = start;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn move_right(&mut self) -> bool {
+        match self.next_boundary() {
+            Some(end) => {
+                self.cursor = end;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn move_home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn move_end(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    /// Byte offset of the start of the line holding the cursor.
+    fn line_start(&self) -> usize {
+        self.before_cursor().rfind('\n').map_or(0, |i| i + 1)
+    }
+
+    /// Byte offset of the end of the line holding the cursor.
+    fn line_end(&self) -> usize {
+        self.after_cursor()
+            .find('\n')
+            .map_or(self.text.len(), |i| self.cursor + i)
+    }
+
+    /// Index of the line holding the cursor, counting from zero.
+    pub fn cursor_line(&self) -> usize {
+        self.before_cursor().matches('\n').count()
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.text.matches('\n').count() + 1
+    }
+
+    /// Display width of the text between the start of the cursor's line and
+    /// the cursor.
+    pub fn cursor_column(&self) -> usize {
+        width(&self.text[self.line_start()..self.cursor])
+    }
+
+    pub fn move_line_start(&mut self) {
+        self.cursor = self.line_start();
+    }
+
+    pub fn move_line_end(&mut self) {
+        self.cursor = self.line_end();
+    }
+
+    /// Places the cursor on the line starting at byte offset `start`, as close
+    /// to display column `column` as that line allows.
+    fn move_to_column(&mut self, start: usize, column: usize) {
+        let line = self.text[start..].split('\n').next().unwrap_or("");
+        let mut offset = 0;
+        let mut used = 0;
+        for grapheme in line.graphemes(true) {
+            used += width(grapheme);
+            if used > column {
+                break;
+            }
+            offset += grapheme.len();
+        }
+        self.cursor = start + offset;
+    }
+
+    /// Moves the cursor to the line above, keeping its column where possible.
+    pub fn move_up(&mut self) -> bool {
+        let start = self.line_start();
+        if start == 0 {
+            return false;
+        }
+        let column = self.cursor_column();
+        let above = self.text[..start - 1].rfind('\n').map_or(0, |i| i + 1);
+        self.move_to_column(above, column);
+        true
+    }
+
+    /// Moves the cursor to the line below, keeping its column where possible.
+    pub fn move_down(&mut self) -> bool {
+        let end = self.line_end();
+        if end == self.text.len() {
+            return false;
+        }
+        let column = self.cursor_column();
+        self.move_to_column(end + 1, column);
+        true
+    }
+
+    /// Byte offset of the end of the word at or after the cursor.
+    fn next_word_end(&self) -> usize {
+        let mut in_word = false;
+        for (index, grapheme) in self.after_cursor().grapheme_indices(true) {
+            if is_word(grapheme) {
+                in_word = true;
+            } else if in_word {
+                return self.cursor + index;
+            }
+        }
+        self.text.len()
+    }
+
+    /// Byte offset of the start of the word before the cursor, where words
+    /// are made of letters and digits, or of anything but whitespace when
+    /// `blank_separated` is set.
+    fn previous_word_start(&self, blank_separated: bool) -> usize {
+        let in_word = |g: &str| {
+            if blank_separated {
+                !is_blank(g)
+            } else {
+                is_word(g)
+            }
+        };
+        let mut seen_word = false;
+        for (index, grapheme) in self.before_cursor().grapheme_indices(true).rev() {
+            if in_word(grapheme) {
+                seen_word = true;
+            } else if seen_word {
+                return index + grapheme.len();
+            }
+        }
+        0
+    }
+
+    /// Removes the text between byte offsets `start` and `end`, leaving the
+    /// cursor at `start`, and returns it.
+    pub fn remove(&mut self, start: usize, end: usize) -> String {
+        let removed: String = self.text.drain(start..end).collect();
+        self.cursor = start;
+        removed
+    }
+
+    /// Moves the cursor to the start of the current or previous word.
+    pub fn move_word_left(&mut self) -> bool {
+        let start = self.previous_word_start(false);
+        let moved = start != self.cursor;
+        self.cursor = start;
+        moved
+    }
+
+    /// Moves the cursor to the end of the current or next word.
+    pub fn move_word_right(&mut self) -> bool {
+        let end = self.next_word_end();
+        let moved = end != self.cursor;
+        self.cursor = end;
+        moved
+    }
+
+    /// Removes everything between the start of the cursor's line and the
+    /// cursor, returning it.
+    pub fn kill_to_start(&mut self) -> String {
+        self.remove(self.line_start(), self.cursor)
+    }
+
+    /// Removes everything from the cursor to the end of its line, returning
+    /// it. At the end of a line the newline is removed, joining the next line.
+    pub fn kill_to_end(&mut self) -> String {
+        let end = match self.line_end() {
+            end if end == self.cursor && end < self.text.len() => end + 1,
+            end => end,
+        };
+        self.remove(self.cursor, end)
+    }
+
+    /// Removes the whitespace separated word before the cursor, returning it.
+    pub fn kill_previous_word(&mut self) -> String {
+        self.remove(self.previous_word_start(true), self.cursor)
+    }
+
+    /// Removes the text up to the end of the next word, returning it.
+    pub fn kill_next_word(&mut self) -> String {
+        self.remove(self.cursor, self.next_word_end())
+    }
+
+    /// Swaps the grapheme before the cursor with the one under it, moving the
+    /// cursor past both. At the end of the text the last two are swapped.
+    pub fn transpose(&mut self) -> bool {
+        let mut graphemes = self.text[..self.cursor].grapheme_indices(true).rev();
+        let (start, middle, end) = match self.next_boundary() {
+            Some(end) => match graphemes.next() {
+                Some((start, _)) => (start, self.cursor, end),
+                None => return false,
+            },
+            None => match (graphemes.next(), graphemes.next()) {
+                (Some((middle, _)), Some((start, _))) => (start, middle, self.cursor),
+                _ => return false,
+            },
+        };
+        let before = self.text[start..middle].to_string();
+        let after = self.text[middle..end].to_string();
+        self.text.replace_range(start..end, &(after + &before));
+        self.cursor = end;
+        true
+    }
+
+    /// Changes the case of the text up to the end of the next word, moving
+    /// the cursor past it.
+    pub fn change_case(&mut self, case: Case) -> bool {
+        let end = self.next_word_end();
+        if end == self.cursor {
+            return false;
+        }
+        let word = &self.text[self.cursor..end];
+        let changed = match case {
+            Case::Upper => word.to_uppercase(),
+            Case::Lower => word.to_lowercase(),
+            Case::Capitalize => {
+                let mut changed = String::new();
+                let mut first = true;
+                for c in word.chars() {
+                    if first && c.is_alphanumeric() {
+                        changed.extend(c.to_uppercase());
+                        first = false;
+                    } else {
+                        changed.extend(c.to_lowercase());
+                    }
+                }
+                changed
+            }
+        };
+        self.text.replace_range(self.cursor..end, &changed);
+        self.cursor += changed.len();
+        true
+    }
+}
+
+/// Maximum number of kills kept by a `KillRing`.
+const KILL_RING_SIZE: usize = 32;
+
+/// Text removed by kill commands, kept so that it can be yanked back in. The
+/// ring turns back through older kills when a yank is replaced.
+#[derive(Clone, Debug, Default)]
+pub struct KillRing {
+    entries: Vec<String>,
+    yank_index: usize,
+}
+
+impl KillRing {
+    pub fn new() -> KillRing {
+        KillRing::default()
+    }
+
+    /// Saves killed text as the most recent kill.
+    pub fn push<S: AsRef<str>>(&mut self, text: S) {
+        let text = text.as_ref();
+        if text.is_empty() {
+            return;
+        }
+        if self.entries.len() == KILL_RING_SIZE {
+            self.entries.remove(0);
+        }
+        self.entries.push(text.to_string());
+        self.yank_index = self.entries.len() - 1;
+    }
+
+    /// Joins killed text to the most recent kill, in front of it when the
+    /// text was killed backwards, so that consecutive kills yank as one.
+    pub fn append<S: AsRef<str>>(&mut self, text: S, backward: bool) {
+        let text = text.as_ref();
+        match self.entries.last_mut() {
+            Some(last) if backward => last.insert_str(0, text),
+            Some(last) => last.push_str(text),
+            None => self.push(text),
+        }
+    }
+
+    /// Returns the most recent kill.
+    pub fn yank(&mut self) -> Option<&str> {
+        self.yank_index = self.entries.len().checked_sub(1)?;
+        self.entries.last().map(String::as_str)
+    }
+
+    /// Moves to the kill before the one last yanked, wrapping around to the
+    /// most recent after the oldest, and returns it.
+    pub fn yank_pop(&mut self) -> Option<&str> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        self.yank_index = match self.yank_index {
+            0 => self.entries.len() - 1,
+            index => index - 1,
+        };
+        Some(&self.entries[self.yank_index])
+    }
+}
+
+/// Earlier states of a `LineBuffer`, for undoing and redoing edits.
+#[derive(Clone, Debug, Default)]
+pub struct UndoStack {
+    undo: Vec<LineBuffer>,
+    redo: Vec<LineBuffer>,
+    /// Whether the last edit recorded inserted a character, so that the
+    /// next one joins it.
+    inserting: bool,
+}
+
+impl UndoStack {
+    pub fn new() -> UndoStack {
+        UndoStack::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.inserting = false;
+    }
+
+    /// Records the buffer as it was before an edit. Consecutive character
+    /// inserts are coalesced into the step of the first one.
+    pub fn record(&mut self, before: LineBuffer, insert: bool) {
+        if !(insert && self.inserting) {
+            self.undo.push(before);
+        }
+        self.inserting = insert;
+        self.redo.clear();
+    }
+
+    /// Ends a run of character inserts, so that the next insert is undone
+    /// separately.
+    pub fn seal(&mut self) {
+        self.inserting = false;
+    }
+
+    /// Returns the buffer as it was before the last edit, keeping `current`
+    /// for redo.
+    pub fn undo(&mut self, current: &LineBuffer) -> Option<LineBuffer> {
+        let previous = self.undo.pop()?;
+        self.redo.push(current.clone());
+        self.inserting = false;
+        Some(previous)
+    }
+
+    /// Returns the buffer as it was before the last undo, keeping `current`
+    /// for undo.
+    pub fn redo(&mut self, current: &LineBuffer) -> Option<LineBuffer> {
+        let next = self.redo.pop()?;
+        self.undo.push(current.clone());
+        self.inserting = false;
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transposed(text: &str, cursor: usize) -> (bool, String, usize) {
+        let mut buffer = LineBuffer::new();
+        buffer.set(text);
+        buffer.set_cursor(cursor);
+        let swapped = buffer.transpose();
+        (swapped, buffer.text().to_string(), buffer.cursor())
+    }
+
+    const FAMILY: &str = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+
+    #[test]
+    fn width_counts_graphemes() {
+        assert_eq!(width("abc"), 3);
+        assert_eq!(width("日本"), 4);
+        assert_eq!(width("e\u{301}"), 1);
+        assert_eq!(width(FAMILY), 2);
+        assert_eq!(width("\x1B[31mred\x1B[0m"), 3);
+        assert_eq!(width(""), 0);
+    }
+
+    #[test]
+    fn truncate_keeps_whole_graphemes() {
+        assert_eq!(truncate("日本語", 5), "日本");
+        assert_eq!(truncate("日本語", 4), "日本");
+        assert_eq!(truncate("日本語", 1), "");
+        assert_eq!(truncate("ae\u{301}b", 2), "ae\u{301}");
+        assert_eq!(truncate(&format!("{}x", FAMILY), 2), FAMILY);
+        assert_eq!(truncate("abc", 10), "abc");
+    }
+
+    #[test]
+    fn advance_wraps_like_a_terminal() {
+        assert_eq!(advance((0, 0), "abc", 10), (0, 3));
+        assert_eq!(advance((0, 0), "abcd", 4), (0, 4));
+        assert_eq!(advance((0, 0), "abcde", 4), (1, 1));
+        assert_eq!(advance((0, 3), "日", 4), (1, 2));
+        assert_eq!(advance((0, 2), "日", 4), (0, 4));
+        assert_eq!(advance((0, 0), "e\u{301}e\u{301}", 2), (0, 2));
+        assert_eq!(advance((0, 3), FAMILY, 4), (1, 2));
+        assert_eq!(advance((0, 2), "a\nbc", 4), (1, 2));
+        assert_eq!(advance((0, 0), "\x1B[1mab\x1B[0m", 2), (0, 2));
+    }
+
+    /// The buffer after `edit` on `text` with the cursor at its end, with
+    /// the cursor shown as `|`.
+    fn edited(text: &str, edit: impl Fn(&mut LineBuffer)) -> String {
+        let mut buffer = LineBuffer::new();
+        buffer.set(text);
+        edit(&mut buffer);
+        format!("{}|{}", buffer.before_cursor(), buffer.after_cursor())
+    }
+
+    #[test]
+    fn edits_work_on_whole_graphemes() {
+        for grapheme in &["é", "e\u{301}", "日", FAMILY] {
+            let text = format!("a{}b", grapheme);
+            let backspace = |b: &mut LineBuffer| {
+                b.move_left();
+                assert!(b.backspace());
+            };
+            assert_eq!(edited(&text, backspace), "a|b");
+            let delete = |b: &mut LineBuffer| {
+                b.move_left();
+                b.move_left();
+                assert!(b.delete());
+            };
+            assert_eq!(edited(&text, delete), "a|b");
+            let left = |b: &mut LineBuffer| {
+                b.move_left();
+                b.move_left();
+            };
+            assert_eq!(edited(&text, left), format!("a|{}b", grapheme));
+            let right = |b: &mut LineBuffer| {
+                b.move_home();
+                b.move_right();
+                b.move_right();
+            };
+            assert_eq!(edited(&text, right), format!("a{}|b", grapheme));
+        }
+    }
+
+    #[test]
+    fn insert_joins_combining_marks() {
+        let insert = |b: &mut LineBuffer| {
+            b.insert('e');
+            b.insert('\u{301}');
+        };
+        assert_eq!(edited("日", insert), "日e\u{301}|");
+        let backspace = |b: &mut LineBuffer| {
+            b.insert('\u{301}');
+            b.backspace();
+        };
+        assert_eq!(edited("xe", backspace), "x|");
+    }
+
+    #[test]
+    fn edges_of_the_line_stop_motions() {
+        let mut buffer = LineBuffer::new();
+        assert!(!buffer.backspace());
+        assert!(!buffer.delete());
+        assert!(!buffer.move_left());
+        assert!(!buffer.move_right());
+        assert!(!buffer.move_up());
+        assert!(!buffer.move_down());
+    }
+
+    #[test]
+    fn vertical_motions_keep_the_display_column() {
+        // The cursor after `日` is at column 2, which is `c` below.
+        let down = |b: &mut LineBuffer| {
+            b.move_home();
+            b.move_right();
+            assert!(b.move_down());
+        };
+        assert_eq!(edited("日本\nabcd", down), "日本\nab|cd");
+        // Column 1 falls inside `日`, so the cursor stays before it.
+        let up = |b: &mut LineBuffer| {
+            b.move_left();
+            b.move_left();
+            b.move_left();
+            assert!(b.move_up());
+        };
+        assert_eq!(edited("日本\nabcd", up), "|日本\nabcd");
+        let combining = |b: &mut LineBuffer| {
+            assert!(b.move_up());
+        };
+        assert_eq!(
+            edited("e\u{301}e\u{301}x\nab", combining),
+            "e\u{301}e\u{301}|x\nab"
+        );
+        assert_eq!(
+            edited(&format!("{}z\nab", FAMILY), combining),
+            format!("{}|z\nab", FAMILY)
+        );
+    }
+
+    #[test]
+    fn set_cursor_stays_on_grapheme_boundaries() {
+        let mut buffer = LineBuffer::new();
+        buffer.set("héllo e\u{301}");
+        buffer.set_cursor(2);
+        assert_eq!(buffer.cursor(), 1);
+        assert_eq!(buffer.before_cursor(), "h");
+        buffer.set_cursor(9);
+        assert_eq!(buffer.cursor(), 7);
+        buffer.set_cursor(100);
+        assert_eq!(buffer.cursor(), buffer.text().len());
+        buffer.set_cursor(3);
+        assert_eq!(buffer.cursor(), 3);
+    }
+
+    #[test]
+    fn transpose_swaps_around_the_cursor() {
+        assert_eq!(transposed("abc", 1), (true, "bac".to_string(), 2));
+        assert_eq!(transposed("abc", 3), (true, "acb".to_string(), 3));
+        assert_eq!(
+            transposed("ae\u{301}", 1),
+            (true, "e\u{301}a".to_string(), 4)
+        );
+    }
+
+    #[test]
+    fn transpose_without_two_graphemes_leaves_the_cursor() {
+        assert_eq!(transposed("a", 1), (false, "a".to_string(), 1));
+        assert_eq!(transposed("a", 0), (false, "a".to_string(), 0));
+        assert_eq!(transposed("", 0), (false, "".to_string(), 0));
+    }
+
+    /// Types `text` into `buffer` one character at a time, recording each
+    /// insert the way the terminal does.
+    fn type_text(buffer: &mut LineBuffer, undo: &mut UndoStack, text: &str) {
+        for c in text.chars() {
+            undo.record(buffer.clone(), true);
+            buffer.insert(c);
+        }
+    }
+
+    #[test]
+    fn undo_coalesces_consecutive_inserts() {
+        let mut buffer = LineBuffer::new();
+        let mut undo = UndoStack::new();
+        type_text(&mut buffer, &mut undo, "abc");
+
+        undo.record(buffer.clone(), false);
+        buffer.backspace();
+        type_text(&mut buffer, &mut undo, "de");
+        assert_eq!(buffer.text(), "abde");
+
+        buffer = undo.undo(&buffer).unwrap();
+        assert_eq!(buffer.text(), "ab");
+        buffer = undo.undo(&buffer).unwrap();
+        assert_eq!(buffer.text(), "abc");
+        buffer = undo.undo(&buffer).unwrap();
+        assert_eq!(buffer.text(), "");
+        assert_eq!(undo.undo(&buffer), None);
+    }
+
+    #[test]
+    fn seal_ends_a_run_of_inserts() {
+        let mut buffer = LineBuffer::new();
+        let mut undo = UndoStack::new();
+        type_text(&mut buffer, &mut undo, "ab");
+        undo.seal();
+        type_text(&mut buffer, &mut undo, "cd");
+
+        buffer = undo.undo(&buffer).unwrap();
+        assert_eq!(buffer.text(), "ab");
+        buffer = undo.undo(&buffer).unwrap();
+        assert_eq!(buffer.text(), "");
+    }
+
+    #[test]
+    fn redo_reapplies_undone_edits_until_the_next_edit() {
+        let mut buffer = LineBuffer::new();
+        let mut undo = UndoStack::new();
+        type_text(&mut buffer, &mut undo, "ab");
+        undo.record(buffer.clone(), false);
+        buffer.insert_str(" yanked");
+
+        buffer = undo.undo(&buffer).unwrap();
+        buffer = undo.undo(&buffer).unwrap();
+        assert_eq!(buffer.text(), "");
+        buffer = undo.redo(&buffer).unwrap();
+        assert_eq!(buffer.text(), "ab");
+        buffer = undo.redo(&buffer).unwrap();
+        assert_eq!(buffer.text(), "ab yanked");
+        assert_eq!(undo.redo(&buffer), None);
+
+        buffer = undo.undo(&buffer).unwrap();
+        assert_eq!(buffer.text(), "ab");
+        // Typing after an undo starts its own step and drops the redo.
+        type_text(&mut buffer, &mut undo, "c");
+        assert_eq!(undo.redo(&buffer), None);
+        buffer = undo.undo(&buffer).unwrap();
+        assert_eq!(buffer.text(), "ab");
+
+        undo.clear();
+        assert_eq!(undo.undo(&buffer), None);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::arguments;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::marker::PhantomData;
 
 pub enum CommandResult {
     Success(String),
@@ -7,5 +8,155 @@ pub enum CommandResult {
     Exit,
 }
 
-pub type Command = fn(HashMap<String, arguments::Argument>) -> CommandResult;
-pub type SpecialCommand = fn(String) -> CommandResult;
+/// A command that can be registered on a `Terminal`.
+///
+/// Commands declaring flags or positionals have their input validated against
+/// them before `execute` is called, with positionals bound to their names.
+/// Commands declaring neither receive arguments unchecked, with positionals
+/// listed under `arguments::POSITIONAL`; this is what closures registered
+/// through `add_command` get.
+pub trait Command {
+    fn flags(&self) -> Vec<arguments::Flag> {
+        Vec::new()
+    }
+
+    fn positionals(&self) -> Vec<arguments::Positional> {
+        Vec::new()
+    }
+
+    /// Whether `input`, everything typed so far, needs more lines before it
+    /// can run. While it does, Enter starts a continuation line instead of
+    /// submitting the input.
+    fn is_incomplete(&self, _input: &str) -> bool {
+        false
+    }
+
+    fn execute(&mut self, args: HashMap<String, arguments::Argument>) -> CommandResult;
+}
+
+impl<F> Command for F
+where
+    F: FnMut(HashMap<String, arguments::Argument>) -> CommandResult,
+{
+    fn execute(&mut self, args: HashMap<String, arguments::Argument>) -> CommandResult {
+        self(args)
+    }
+}
+
+pub type SpecialCommand = Box<dyn FnMut(String) -> CommandResult>;
+
+/// A tree of commands keyed by name, so that `db migrate up` is the `up`
+/// child of `migrate` under `db`. Nodes without a command group their
+/// children.
+#[derive(Default)]
+pub struct CommandTree {
+    command: Option<Box<dyn Command>>,
+    description: String,
+    children: BTreeMap<String, CommandTree>,
+}
+
+impl CommandTree {
+    pub fn new() -> CommandTree {
+        CommandTree::default()
+    }
+
+    /// Returns the node at `path`, creating any missing nodes along it.
+    pub fn entry<S: AsRef<str>>(&mut self, path: &[S]) -> &mut CommandTree {
+        path.iter().fold(self, |node, name| {
+            node.children.entry(name.as_ref().to_string()).or_default()
+        })
+    }
+
+    pub fn insert<S: AsRef<str>>(
+        &mut self,
+        path: &[S],
+        command: Box<dyn Command>,
+        description: &str,
+    ) {
+        let node = self.entry(path);
+        node.command = Some(command);
+        node.description = description.to_string();
+    }
+
+    pub fn describe<S: AsRef<str>>(&mut self, path: &[S], description: &str) {
+        self.entry(path).description = description.to_string();
+    }
+
+    /// Follows `words` down the tree for as long as they name children,
+    /// returning the node reached and how many words were consumed.
+    pub fn find<S: AsRef<str>>(&self, words: &[S]) -> (&CommandTree, usize) {
+        let mut node = self;
+        let mut depth = 0;
+        for word in words.iter() {
+            match node.children.get(word.as_ref()) {
+                Some(child) => node = child,
+                None => break,
+            }
+            depth += 1;
+        }
+        (node, depth)
+    }
+
+    pub fn find_mut<S: AsRef<str>>(&mut self, words: &[S]) -> (&mut CommandTree, usize) {
+        let (_, depth) = self.find(words);
+        let node = words[..depth].iter().fold(self, |node, word| {
+            node.children.get_mut(word.as_ref()).unwrap()
+        });
+        (node, depth)
+    }
+
+    pub fn command(&self) -> Option<&dyn Command> {
+        self.command.as_deref()
+    }
+
+    pub fn command_mut(&mut self) -> Option<&mut (dyn Command + 'static)> {
+        self.command.as_deref_mut()
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = (&String, &CommandTree)> {
+        self.children.iter()
+    }
+}
+
+/// A command whose arguments are parsed into `A` before `f` is called.
+pub struct Typed<A, F> {
+    f: F,
+    args: PhantomData<fn(A)>,
+}
+
+/// Wraps `f` so that its flags are declared by, and its input parsed into, `A`.
+pub fn typed<A, F>(f: F) -> Typed<A, F>
+where
+    A: arguments::PromptArgs,
+    F: FnMut(A) -> CommandResult,
+{
+    Typed {
+        f,
+        args: PhantomData,
+    }
+}
+
+impl<A, F> Command for Typed<A, F>
+where
+    A: arguments::PromptArgs,
+    F: FnMut(A) -> CommandResult,
+{
+    fn flags(&self) -> Vec<arguments::Flag> {
+        A::flags()
+    }
+
+    fn positionals(&self) -> Vec<arguments::Positional> {
+        A::positionals()
+    }
+
+    fn execute(&mut self, args: HashMap<String, arguments::Argument>) -> CommandResult {
+        match A::from_arguments(&args) {
+            Ok(args) => (self.f)(args),
+            Err(e) => CommandResult::Failure(e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,443 @@
+use crate::arguments::{ArgumentType, Flag, Positional};
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+/// Produces candidate values for an argument from the text typed so far.
+/// Candidates are matched against that text afterwards, so completers only
+/// need to narrow them down where it is cheaper to do so.
+pub type Completer = Rc<dyn Fn(&str) -> Vec<String>>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Suggestion {
+    pub value: String,
+    pub description: String,
+    /// Indices of the characters of `value` matched by the typed text.
+    pub matched: Vec<usize>,
+}
+
+impl Suggestion {
+    pub fn new<S: AsRef<str>, D: AsRef<str>>(value: S, description: D) -> Suggestion {
+        Suggestion {
+            value: value.as_ref().to_string(),
+            description: description.as_ref().to_string(),
+            matched: Vec::new(),
+        }
+    }
+}
+
+/// How the typed text selects suggestions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MatchMode {
+    /// Suggestions starting with the typed text.
+    #[default]
+    Prefix,
+    /// Suggestions containing the typed text, ignoring case.
+    Substring,
+    /// Suggestions containing the typed characters in order, ignoring case,
+    /// best matches first.
+    Fuzzy,
+}
+
+/// Matches `pattern` against `candidate`, returning a score, higher being a
+/// better match, and the indices of the matched characters.
+pub fn match_candidate(
+    mode: MatchMode,
+    pattern: &str,
+    candidate: &str,
+) -> Option<(i64, Vec<usize>)> {
+    match mode {
+        MatchMode::Prefix => {
+            if !candidate.starts_with(pattern) {
+                return None;
+            }
+            Some((0, (0..pattern.chars().count()).collect()))
+        }
+        MatchMode::Substring => {
+            let pattern: Vec<char> = pattern.chars().flat_map(char::to_lowercase).collect();
+            let candidate: Vec<char> = candidate.chars().collect();
+            if pattern.is_empty() {
+                return Some((0, Vec::new()));
+            }
+            (0..candidate.len())
+                .find(|&start| {
+                    candidate.len() - start >= pattern.len()
+                        && pattern
+                            .iter()
+                            .zip(candidate[start..].iter())
+                            .all(|(p, c)| c.to_lowercase().eq(std::iter::once(*p)))
+                })
+                .map(|start| (-(start as i64), (start..start + pattern.len()).collect()))
+        }
+        MatchMode::Fuzzy => fuzzy_match(pattern, candidate),
+    }
+}
+
+/// Finds the alignment of `pattern` in `candidate` with the best score, so
+/// that `ip` matches the end of `wtfismyip` rather than its first `i`.
+fn fuzzy_match(pattern: &str, candidate: &str) -> Option<(i64, Vec<usize>)> {
+    let pattern: Vec<char> = pattern.chars().flat_map(char::to_lowercase).collect();
+    let candidate: Vec<char> = candidate.chars().collect();
+    if pattern.is_empty() {
+        return Some((0, Vec::new()));
+    }
+
+    // Score of a character matched at `index`, apart from its distance to
+    // the previous match.
+    let base = |index: usize| {
+        let at_boundary = index == 0
+            || !candidate[index - 1].is_alphanumeric()
+            || (candidate[index - 1].is_lowercase() && candidate[index].is_uppercase());
+        if at_boundary {
+            26
+        } else {
+            16
+        }
+    };
+    let matches = |p: char, index: usize| candidate[index].to_lowercase().eq(std::iter::once(p));
+
+    // best[i][j]: the best score of the first `i + 1` pattern characters
+    // with the last one matched at `j`, and where the one before it matched.
+    let mut best: Vec<Vec<Option<(i64, usize)>>> = Vec::with_capacity(pattern.len());
+    for (i, &p) in pattern.iter().enumerate() {
+        let row = (0..candidate.len())
+            .map(|j| {
+                if !matches(p, j) {
+                    return None;
+                }
+                if i == 0 {
+                    return Some((base(j) - j.min(10) as i64, 0));
+                }
+                (0..j)
+                    .filter_map(|k| {
+                        let (score, _) = best[i - 1][k]?;
+                        let distance = if k + 1 == j {
+                            15
+                        } else {
+                            -((j - k - 1).min(10) as i64)
+                        };
+                        Some((score + distance + base(j), k))
+                    })
+                    .max_by_key(|&(score, _)| score)
+            })
+            .collect();
+        best.push(row);
+    }
+
+    let (mut index, (score, _)) = best[pattern.len() - 1]
+        .iter()
+        .enumerate()
+        .filter_map(|(j, cell)| cell.map(|cell| (j, cell)))
+        .max_by_key(|&(_, (score, _))| score)?;
+    let mut matched = vec![index; pattern.len()];
+    for i in (1..pattern.len()).rev() {
+        let (_, previous) = best[i][index]?;
+        index = previous;
+        matched[i - 1] = index;
+    }
+
+    Some((score, matched))
+}
+
+/// Keeps the suggestions matched by `pattern`, recording which characters
+/// matched. Fuzzy and substring matches are ordered best first.
+pub fn filter_suggestions(
+    mode: MatchMode,
+    pattern: &str,
+    suggestions: Vec<Suggestion>,
+) -> Vec<Suggestion> {
+    let mut scored: Vec<(i64, Suggestion)> = suggestions
+        .into_iter()
+        .filter_map(|mut suggestion| {
+            let (score, matched) = match_candidate(mode, pattern, &suggestion.value)?;
+            suggestion.matched = matched;
+            Some((score, suggestion))
+        })
+        .collect();
+
+    if mode != MatchMode::Prefix {
+        scored.sort_by(|(a, _), (b, _)| b.cmp(a));
+    }
+
+    scored
+        .into_iter()
+        .map(|(_, suggestion)| suggestion)
+        .collect()
+}
+
+/// A completer offering a fixed list of values, such as enum variants.
+pub fn values<S: AsRef<str>>(values: &[S]) -> Completer {
+    let values: Vec<String> = values.iter().map(|v| v.as_ref().to_string()).collect();
+    Rc::new(move |_| values.clone())
+}
+
+/// Suggests flags and argument values for a command, given the words typed
+/// after its name and the start of the word being typed.
+pub fn complete_arguments(
+    flags: &[Flag],
+    positionals: &[Positional],
+    words: &[String],
+    partial: &str,
+) -> Vec<Suggestion> {
+    let mut positional_index = 0;
+    let mut pending_flag: Option<&Flag> = None;
+    let mut end_of_flags = false;
+
+    for word in words.iter() {
+        if pending_flag.take().is_some() {
+            continue;
+        }
+        if end_of_flags || !word.starts_with('-') || word == "-" {
+            positional_index += 1;
+        } else if word == "--" {
+            end_of_flags = true;
+        } else {
+            let key = word.trim_start_matches('-');
+            pending_flag = flags.iter().find(|f| {
+                f.kind != ArgumentType::Bool && (f.name == key || f.short.as_deref() == Some(key))
+            });
+        }
+    }
+
+    if let Some(flag) = pending_flag {
+        return candidates(&flag.completer, partial);
+    }
+
+    let mut suggestions = Vec::new();
+    if !end_of_flags && (partial.is_empty() || partial.starts_with('-')) {
+        suggestions.extend(
+            flags
+                .iter()
+                .filter(|f| f.multiple || !words.iter().any(|w| is_flag_word(w, f)))
+                .map(|f| Suggestion::new(format!("-{}", f.name), &f.help)),
+        );
+    }
+
+    let positional = positionals
+        .get(positional_index)
+        .or_else(|| positionals.last().filter(|p| p.multiple));
+    if let Some(positional) = positional {
+        suggestions.extend(candidates(&positional.completer, partial));
+    }
+
+    suggestions
+}
+
+fn is_flag_word(word: &str, flag: &Flag) -> bool {
+    let key = word.trim_start_matches('-');
+    word.starts_with('-') && (flag.name == key || flag.short.as_deref() == Some(key))
+}
+
+fn candidates(completer: &Option<Completer>, partial: &str) -> Vec<Suggestion> {
+    match completer {
+        Some(completer) => completer(partial)
+            .into_iter()
+            .map(|c| Suggestion::new(c, ""))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// A completer listing files and directories, for arguments that are paths.
+pub fn paths() -> Completer {
+    Rc::new(complete_path)
+}
+
+/// Lists the entries of the directory named by `partial`, up to its last
+/// slash. Relative paths are resolved from the current working directory, a
+/// leading `~` is expanded, hidden files are only listed once a `.` has been
+/// typed, and directories end with a slash.
+pub fn complete_path(partial: &str) -> Vec<String> {
+    if partial == "~" {
+        return vec!["~/".to_string()];
+    }
+
+    let (dir, prefix) = match partial.rfind('/') {
+        Some(index) => partial.split_at(index + 1),
+        None => ("", partial),
+    };
+
+    let search = if dir.is_empty() {
+        PathBuf::from(".")
+    } else {
+        expand_home(dir)
+    };
+
+    let entries = match fs::read_dir(search) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut candidates: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if name.starts_with('.') && !prefix.starts_with('.') {
+                return None;
+            }
+            let is_dir = entry.path().is_dir();
+            Some(format!("{}{}{}", dir, name, if is_dir { "/" } else { "" }))
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
+
+/// The path with a leading `~` or `~/` replaced by the home directory, as
+/// the shell does. Completed paths keep the `~`, so arguments expand it when
+/// they are read.
+pub fn expand_home(path: &str) -> PathBuf {
+    let rest = match path.strip_prefix('~') {
+        Some("") => "",
+        Some(rest) if rest.starts_with('/') => &rest[1..],
+        _ => return PathBuf::from(path),
+    };
+    match env::var_os("HOME") {
+        Some(home) => PathBuf::from(home).join(rest),
+        None => PathBuf::from(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_match_prefers_the_best_alignment() {
+        let (_, matched) = fuzzy_match("ip", "wtfismyip").unwrap();
+        assert_eq!(matched, vec![7, 8]);
+
+        let (_, matched) = fuzzy_match("gc", "git-commit").unwrap();
+        assert_eq!(matched, vec![0, 4]);
+    }
+
+    #[test]
+    fn fuzzy_match_ranks_consecutive_matches_first() {
+        let suggestions = vec![Suggestion::new("impatient", ""), Suggestion::new("zip", "")];
+        let filtered = filter_suggestions(MatchMode::Fuzzy, "ip", suggestions);
+        assert_eq!(filtered[0].value, "zip");
+        assert_eq!(filtered[1].matched, vec![0, 2]);
+    }
+
+    #[test]
+    fn fuzzy_match_rejects_missing_characters() {
+        assert_eq!(fuzzy_match("xyz", "wtfismyip"), None);
+        assert_eq!(fuzzy_match("", "abc"), Some((0, Vec::new())));
+    }
+
+    #[test]
+    fn complete_path_lists_directory_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "").unwrap();
+        fs::write(dir.path().join(".hidden"), "").unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        let root = format!("{}/", dir.path().display());
+
+        let listed = complete_path(&root);
+        assert_eq!(
+            listed,
+            vec![format!("{}a.txt", root), format!("{}sub/", root)]
+        );
+
+        let listed = complete_path(&format!("{}.", root));
+        assert!(listed.contains(&format!("{}.hidden", root)));
+        assert!(complete_path(&format!("{}missing/", root)).is_empty());
+    }
+
+    #[test]
+    fn home_is_expanded_in_completions_and_values() {
+        use crate::arguments::FromValue;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("file"), "").unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        env::set_var("HOME", dir.path());
+
+        assert_eq!(complete_path("~"), vec!["~/"]);
+        assert_eq!(complete_path("~/"), vec!["~/file", "~/sub/"]);
+        assert_eq!(complete_path("~/sub/"), Vec::<String>::new());
+
+        assert_eq!(expand_home("~"), dir.path());
+        assert_eq!(expand_home("~/file"), dir.path().join("file"));
+        assert_eq!(expand_home("~user/file"), PathBuf::from("~user/file"));
+        assert_eq!(expand_home("a/~/b"), PathBuf::from("a/~/b"));
+        assert_eq!(
+            PathBuf::from_value("path", "~/file").unwrap(),
+            dir.path().join("file")
+        );
+        assert_eq!(
+            PathBuf::from_value("path", "rel/file").unwrap(),
+            PathBuf::from("rel/file")
+        );
+    }
+
+    fn completed(line: &str) -> Vec<String> {
+        let flags = [
+            Flag::string("format").short("f").values(&["json", "csv"]),
+            Flag::bool("verbose").short("v").help("More output"),
+            Flag::string("tag").multiple(),
+        ];
+        let positionals = [
+            Positional::new("source").values(&["s1", "s2"]),
+            Positional::new("targets").values(&["t1"]).multiple(),
+        ];
+        let mut words: Vec<String> = line.split(' ').map(str::to_string).collect();
+        let partial = words.pop().unwrap();
+        complete_arguments(&flags, &positionals, &words, &partial)
+            .into_iter()
+            .map(|suggestion| suggestion.value)
+            .collect()
+    }
+
+    #[test]
+    fn complete_arguments_offers_flags_and_the_next_positional() {
+        assert_eq!(
+            completed(""),
+            vec!["-format", "-verbose", "-tag", "s1", "s2"]
+        );
+        assert_eq!(
+            completed("-"),
+            vec!["-format", "-verbose", "-tag", "s1", "s2"]
+        );
+        // Only values are offered once a word without a dash is started.
+        assert_eq!(completed("s"), vec!["s1", "s2"]);
+    }
+
+    #[test]
+    fn complete_arguments_completes_flag_values() {
+        assert_eq!(completed("--format "), vec!["json", "csv"]);
+        assert_eq!(completed("-f j"), vec!["json", "csv"]);
+        // Bool flags take no value, so the positional comes next.
+        assert_eq!(completed("-v s"), vec!["s1", "s2"]);
+        // A flag's value is not counted as a positional.
+        assert_eq!(completed("-f json s"), vec!["s1", "s2"]);
+    }
+
+    #[test]
+    fn complete_arguments_counts_positionals() {
+        assert_eq!(completed("s1 "), vec!["-format", "-verbose", "-tag", "t1"]);
+        // The last positional takes every remaining word.
+        assert_eq!(completed("s1 t1 t"), vec!["t1"]);
+        let positionals = [Positional::new("only").values(&["x"])];
+        let words = vec!["x".to_string()];
+        assert!(complete_arguments(&[], &positionals, &words, "").is_empty());
+    }
+
+    #[test]
+    fn complete_arguments_hides_flags_already_given() {
+        assert_eq!(completed("-v --format csv "), vec!["-tag", "s1", "s2"]);
+        assert_eq!(
+            completed("-f csv --tag a "),
+            vec!["-verbose", "-tag", "s1", "s2"]
+        );
+    }
+
+    #[test]
+    fn complete_arguments_stops_offering_flags_after_double_dash() {
+        assert_eq!(completed("-- "), vec!["s1", "s2"]);
+        assert_eq!(completed("-- -v -"), vec!["t1"]);
+    }
+}
@@ -0,0 +1,391 @@
+use crate::completion::MatchMode;
+use crate::history::Dedup;
+use crate::keymap::Action;
+use crate::prompt::{Prompt, Segment};
+use crate::terminal::EditMode;
+use crate::theme::{self, Style, Theme};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Settings of a `Terminal` read from TOML, applied with
+/// `Terminal::apply_config`. Anything left out keeps the terminal's current
+/// setting.
+///
+/// ```toml
+/// [prompt]
+/// continuation = "  > "
+/// segments = [
+///     { kind = "cwd", style = { fg = "blue" } },
+///     { kind = "git-branch", before = " (", after = ")" },
+///     { kind = "text", text = " $ " },
+/// ]
+///
+/// [theme]
+/// preset = "light"
+/// failure = { fg = "red", bold = true }
+///
+/// [keymap]
+/// mode = "vi"
+/// unbind = ["ctrl-z"]
+///
+/// [keymap.bindings]
+/// "ctrl-x ctrl-e" = "edit-in-editor"
+///
+/// [history]
+/// path = "~/.my_app_history"
+/// dedup = "all"
+///
+/// [completion]
+/// mode = "fuzzy"
+/// ```
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub prompt: PromptConfig,
+    pub theme: ThemeConfig,
+    pub keymap: KeymapConfig,
+    pub history: HistoryConfig,
+    pub completion: CompletionConfig,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PromptConfig {
+    pub segments: Option<Vec<SegmentConfig>>,
+    /// Segments of the right-aligned prompt.
+    pub right_segments: Option<Vec<SegmentConfig>>,
+    pub continuation: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SegmentKind {
+    Text,
+    Cwd,
+    GitBranch,
+    Status,
+    Time,
+    Duration,
+    Name,
+}
+
+/// A prompt segment, like the ones built with `prompt::Segment`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SegmentConfig {
+    pub kind: SegmentKind,
+    /// Text of a `text` segment.
+    #[serde(default)]
+    pub text: String,
+    /// Shown by a `status` segment after a command succeeded.
+    #[serde(default)]
+    pub success: String,
+    /// Shown by a `status` segment after a command failed.
+    #[serde(default)]
+    pub failure: String,
+    pub style: Option<Style>,
+    #[serde(default)]
+    pub before: String,
+    #[serde(default)]
+    pub after: String,
+}
+
+impl SegmentConfig {
+    pub fn segment(&self) -> Segment {
+        let segment = match self.kind {
+            SegmentKind::Text => Segment::text(&self.text),
+            SegmentKind::Cwd => Segment::cwd(),
+            SegmentKind::GitBranch => Segment::git_branch(),
+            SegmentKind::Status => Segment::status(&self.success, &self.failure),
+            SegmentKind::Time => Segment::time(),
+            SegmentKind::Duration => Segment::duration(),
+            SegmentKind::Name => Segment::name(),
+        };
+        let segment = segment.around(&self.before, &self.after);
+        match self.style {
+            Some(style) => segment.style(style),
+            None => segment,
+        }
+    }
+}
+
+/// Builds a prompt from its segments.
+pub fn prompt(segments: &[SegmentConfig]) -> Prompt {
+    segments.iter().fold(Prompt::new(), |prompt, segment| {
+        prompt.segment(segment.segment())
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    Dark,
+    Light,
+    NoColor,
+}
+
+/// A preset theme and styles replacing some of its own.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    pub preset: Option<Preset>,
+    pub prompt: Option<Style>,
+    pub input: Option<Style>,
+    pub popup_key: Option<Style>,
+    pub popup_description: Option<Style>,
+    pub popup_selected_key: Option<Style>,
+    pub popup_selected_description: Option<Style>,
+    pub success: Option<Style>,
+    pub failure: Option<Style>,
+    pub hint: Option<Style>,
+}
+
+impl ThemeConfig {
+    /// Switches `theme` to the preset, if one is set, then replaces the
+    /// styles that are set. When `NO_COLOR` is set, presets are replaced by
+    /// `Theme::no_color()` and styles lose their colors.
+    pub fn apply(&self, theme: &mut Theme) {
+        match self.preset {
+            Some(Preset::Dark) => *theme = Theme::dark().or_no_color(),
+            Some(Preset::Light) => *theme = Theme::light().or_no_color(),
+            Some(Preset::NoColor) => *theme = Theme::no_color(),
+            None => {}
+        }
+        let no_color = theme::no_color_requested();
+        let set = |themed: &mut Style, style: Option<Style>| match style {
+            Some(style) if no_color => *themed = style.without_colors(),
+            Some(style) => *themed = style,
+            None => {}
+        };
+        set(&mut theme.prompt, self.prompt);
+        set(&mut theme.input, self.input);
+        set(&mut theme.popup_key, self.popup_key);
+        set(&mut theme.popup_description, self.popup_description);
+        set(&mut theme.popup_selected_key, self.popup_selected_key);
+        set(
+            &mut theme.popup_selected_description,
+            self.popup_selected_description,
+        );
+        set(&mut theme.success, self.success);
+        set(&mut theme.failure, self.failure);
+        set(&mut theme.hint, self.hint);
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeymapConfig {
+    pub mode: Option<EditMode>,
+    /// Actions bound to key sequences written like `ctrl-x ctrl-e`, as
+    /// parsed by `keymap::parse_keys`.
+    pub bindings: HashMap<String, Action>,
+    /// Key sequences whose default bindings are removed.
+    pub unbind: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// File the history is loaded from and saved to. A leading `~` stands
+    /// for the home directory.
+    pub path: Option<PathBuf>,
+    pub size: Option<usize>,
+    pub dedup: Option<Dedup>,
+    pub ignore_space: Option<bool>,
+}
+
+impl HistoryConfig {
+    /// The history file, with a leading `~` expanded.
+    pub fn path(&self) -> Option<PathBuf> {
+        let path = self.path.as_ref()?;
+        match (path.strip_prefix("~"), env::var_os("HOME")) {
+            (Ok(rest), Some(home)) => Some(PathBuf::from(home).join(rest)),
+            _ => Some(path.clone()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompletionConfig {
+    pub mode: Option<MatchMode>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    /// A key sequence that could not be parsed, and why.
+    Keys(String, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "{}: {}", path.display(), e),
+            ConfigError::Keys(keys, e) => write!(f, "invalid key binding `{}`: {}", keys, e),
+        }
+    }
+}
+
+impl FromStr for Config {
+    type Err = toml::de::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s)
+    }
+}
+
+impl Config {
+    /// Files read for `app`, from least to most important: `config.toml` in
+    /// the `app` directory of each of `$XDG_CONFIG_DIRS`, which defaults to
+    /// `/etc/xdg`, then of `$XDG_CONFIG_HOME`, which defaults to
+    /// `~/.config`, so users can override settings of the system.
+    pub fn paths(app: &str) -> Vec<PathBuf> {
+        let non_empty = |name: &str| env::var_os(name).filter(|value| !value.is_empty());
+
+        let system = non_empty("XDG_CONFIG_DIRS").unwrap_or_else(|| "/etc/xdg".into());
+        let mut dirs: Vec<PathBuf> = env::split_paths(&system).collect();
+        dirs.reverse();
+
+        let user = non_empty("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| non_empty("HOME").map(|home| PathBuf::from(home).join(".config")));
+        dirs.extend(user);
+
+        dirs.into_iter()
+            .map(|dir| dir.join(app).join("config.toml"))
+            .collect()
+    }
+
+    /// Loads the configuration of `app` from the files named by
+    /// `Config::paths`.
+    pub fn load(app: &str) -> Result<Config, ConfigError> {
+        Config::load_files(&Config::paths(app))
+    }
+
+    /// Loads files from least to most important, skipping missing ones.
+    /// Settings of later files override those of earlier ones, tables being
+    /// merged key by key.
+    pub fn load_files<P: AsRef<Path>>(paths: &[P]) -> Result<Config, ConfigError> {
+        let mut merged = toml::Table::new();
+        let mut last = PathBuf::new();
+        for path in paths {
+            let path = path.as_ref();
+            let contents = match fs::read_to_string(path) {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(ConfigError::Io(path.to_path_buf(), e)),
+            };
+            // Each file is checked on its own so errors name the file.
+            let parse_error = |e| ConfigError::Parse(path.to_path_buf(), e);
+            let table: toml::Table = toml::from_str(&contents).map_err(parse_error)?;
+            toml::Value::Table(table.clone())
+                .try_into::<Config>()
+                .map_err(parse_error)?;
+            merge(&mut merged, table);
+            last = path.to_path_buf();
+        }
+        toml::Value::Table(merged)
+            .try_into()
+            .map_err(|e| ConfigError::Parse(last, e))
+    }
+}
+
+/// Merges `overrides` into `table`, recursing into tables found in both.
+fn merge(table: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (table.get_mut(&key), value) {
+            (Some(toml::Value::Table(table)), toml::Value::Table(overrides)) => {
+                merge(table, overrides)
+            }
+            (_, value) => {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::theme::Color;
+
+    #[test]
+    fn later_files_override_earlier_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let system = dir.path().join("system.toml");
+        let user = dir.path().join("user.toml");
+        fs::write(
+            &system,
+            r#"
+            [prompt]
+            continuation = ". "
+            [theme]
+            preset = "light"
+            failure = { fg = "red" }
+            [history]
+            size = 100
+            dedup = "all"
+            "#,
+        )
+        .unwrap();
+        fs::write(
+            &user,
+            r#"
+            [theme]
+            failure = { fg = "yellow", bold = true }
+            [history]
+            size = 50
+            "#,
+        )
+        .unwrap();
+
+        let missing = dir.path().join("missing.toml");
+        let config = Config::load_files(&[&system, &missing, &user]).unwrap();
+        assert_eq!(config.prompt.continuation.as_deref(), Some(". "));
+        assert_eq!(config.theme.preset, Some(Preset::Light));
+        assert_eq!(
+            config.theme.failure,
+            Some(Style::new().fg(Color::Yellow).bold())
+        );
+        assert_eq!(config.history.size, Some(50));
+        assert_eq!(config.history.dedup, Some(Dedup::All));
+    }
+
+    #[test]
+    fn errors_name_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let good = dir.path().join("good.toml");
+        let bad = dir.path().join("bad.toml");
+        fs::write(&good, "[history]\nsize = 10\n").unwrap();
+        fs::write(&bad, "[history]\nsize = \"ten\"\n").unwrap();
+
+        match Config::load_files(&[&good, &bad]) {
+            Err(ConfigError::Parse(path, _)) => assert_eq!(path, bad),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+
+        fs::write(&bad, "[histroy]\n").unwrap();
+        match Config::load_files(&[&bad, &good]) {
+            Err(ConfigError::Parse(path, _)) => assert_eq!(path, bad),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn merge_replaces_values_and_merges_tables() {
+        let mut table: toml::Table = toml::from_str("a = 1\n[t]\nx = 1\ny = [1]\n").unwrap();
+        let overrides: toml::Table = toml::from_str("a = 2\n[t]\ny = [2]\n").unwrap();
+        merge(&mut table, overrides);
+        let expected: toml::Table = toml::from_str("a = 2\n[t]\nx = 1\ny = [2]\n").unwrap();
+        assert_eq!(table, expected);
+    }
+}
@@ -0,0 +1,325 @@
+use crate::arguments::{self, ArgumentResult, ParseError, Token};
+use crate::command::CommandTree;
+use crate::theme::{Color, Style};
+use std::ops::Range;
+use termion::style;
+
+/// What a part of the line is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Kind {
+    /// A word naming a registered command or subcommand.
+    Command,
+    /// A first word that names no command.
+    UnknownCommand,
+    Flag,
+    /// A word with quotes, or a quote still open at the end of the line.
+    Quoted,
+    /// The character starting a special command.
+    Special,
+    Argument,
+}
+
+/// A byte range of the line and what it is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub kind: Kind,
+}
+
+/// Colors the line as it is typed.
+pub trait Highlighter {
+    /// Styles for byte ranges of `line`, given the `spans` the line was split
+    /// into. Ranges must not overlap; text outside of them keeps the input
+    /// style of the theme. Lines of a multi-line input are highlighted one
+    /// at a time.
+    fn highlight(&self, line: &str, spans: &[Span]) -> Vec<(Range<usize>, Style)>;
+}
+
+impl<F> Highlighter for F
+where
+    F: Fn(&str, &[Span]) -> Vec<(Range<usize>, Style)>,
+{
+    fn highlight(&self, line: &str, spans: &[Span]) -> Vec<(Range<usize>, Style)> {
+        self(line, spans)
+    }
+}
+
+/// A highlighter giving each kind of span a style.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Styles {
+    pub command: Style,
+    pub unknown_command: Style,
+    pub flag: Style,
+    pub quoted: Style,
+    pub special: Style,
+    pub argument: Style,
+}
+
+impl Default for Styles {
+    fn default() -> Self {
+        Styles {
+            command: Style::new().fg(Color::Green),
+            unknown_command: Style::new().fg(Color::Red),
+            flag: Style::new().fg(Color::Cyan),
+            quoted: Style::new().fg(Color::Yellow),
+            special: Style::new().fg(Color::Magenta).bold(),
+            argument: Style::new(),
+        }
+    }
+}
+
+impl Highlighter for Styles {
+    fn highlight(&self, _line: &str, spans: &[Span]) -> Vec<(Range<usize>, Style)> {
+        spans
+            .iter()
+            .map(|span| {
+                let style = match span.kind {
+                    Kind::Command => self.command,
+                    Kind::UnknownCommand => self.unknown_command,
+                    Kind::Flag => self.flag,
+                    Kind::Quoted => self.quoted,
+                    Kind::Special => self.special,
+                    Kind::Argument => self.argument,
+                };
+                (span.start..span.end, style)
+            })
+            .filter(|(_, style)| !style.is_plain())
+            .collect()
+    }
+}
+
+/// Splits the input into spans, with the words of `commands` told apart from
+/// unknown ones. Input starting with a character of `special` is a special
+/// command, whose argument is left as it is.
+pub fn spans(input: &str, commands: &CommandTree, special: &[char]) -> Vec<Span> {
+    if let Ok(ArgumentResult::Special(c, _)) = arguments::parse_arguments(input) {
+        let kind = if special.contains(&c) {
+            Kind::Special
+        } else {
+            Kind::UnknownCommand
+        };
+        return vec![Span {
+            start: 0,
+            end: c.len_utf8(),
+            kind,
+        }];
+    }
+
+    // A quote or escape left open while typing ends the line, and the words
+    // before it are still highlighted.
+    let (tokens, open) = match arguments::tokenize(input) {
+        Ok(tokens) => (tokens, None),
+        Err(ParseError::UnterminatedQuote(_, start)) => {
+            (tokenize_before(input, start), Some(start))
+        }
+        Err(_) => (tokenize_before(input, input.len() - 1), None),
+    };
+
+    let words: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+    let (_, depth) = commands.find(&words);
+
+    let mut spans = Vec::new();
+    let mut end_of_flags = false;
+    for (index, token) in tokens.iter().enumerate() {
+        let kind = if index < depth {
+            Kind::Command
+        } else if index == 0 {
+            Kind::UnknownCommand
+        } else if token.quoted {
+            Kind::Quoted
+        } else if token.is_flag() && !end_of_flags {
+            end_of_flags = token.text == "--";
+            Kind::Flag
+        } else {
+            Kind::Argument
+        };
+        spans.push(Span {
+            start: token.start,
+            end: token.end,
+            kind,
+        });
+    }
+    if let Some(start) = open {
+        // The word the open quote belongs to starts before it when the quote
+        // is in the middle of a word.
+        let start = input[..start]
+            .rfind(char::is_whitespace)
+            .map_or(0, |i| i + 1)
+            .max(spans.last().map_or(0, |s| s.end));
+        spans.push(Span {
+            start,
+            end: input.len(),
+            kind: Kind::Quoted,
+        });
+    }
+    spans
+}
+
+/// Tokens of the input before `end`, dropping the word cut at `end`.
+fn tokenize_before(input: &str, end: usize) -> Vec<Token> {
+    let mut tokens = arguments::tokenize(&input[..end]).unwrap_or_default();
+    if tokens.last().is_some_and(|t| t.end == end) {
+        tokens.pop();
+    }
+    tokens
+}
+
+/// Spans of one line of the input, starting at byte `start` and ending at
+/// `end`, with offsets relative to the line.
+pub fn line_spans(spans: &[Span], start: usize, end: usize) -> Vec<Span> {
+    spans
+        .iter()
+        .filter(|span| span.start < end && span.end > start)
+        .map(|span| Span {
+            start: span.start.max(start) - start,
+            end: span.end.min(end) - start,
+            kind: span.kind,
+        })
+        .collect()
+}
+
+/// Writes `line` in `base` with the ranges in their styles. Ranges that
+/// overlap earlier ones or are not on character boundaries are skipped.
+pub fn paint(line: &str, mut styled: Vec<(Range<usize>, Style)>, base: &Style) -> String {
+    styled.sort_by_key(|(range, _)| range.start);
+    let mut output = base.to_string();
+    let mut written = 0;
+    for (range, style) in styled {
+        if range.start < written
+            || range.start > range.end
+            || range.end > line.len()
+            || !line.is_char_boundary(range.start)
+            || !line.is_char_boundary(range.end)
+        {
+            continue;
+        }
+        output.push_str(&line[written..range.start]);
+        output.push_str(&format!(
+            "{}{}{}{}",
+            style,
+            &line[range.clone()],
+            style::Reset,
+            base
+        ));
+        written = range.end;
+    }
+    output.push_str(&line[written..]);
+    if !base.is_plain() {
+        output.push_str(style::Reset.as_ref());
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands() -> CommandTree {
+        let mut commands = CommandTree::new();
+        commands.describe(&["echo"], "print");
+        commands.describe(&["git", "log"], "show commits");
+        commands
+    }
+
+    fn kinds(input: &str) -> Vec<(usize, usize, Kind)> {
+        spans(input, &commands(), &['!'])
+            .into_iter()
+            .map(|span| (span.start, span.end, span.kind))
+            .collect()
+    }
+
+    #[test]
+    fn spans_tell_words_apart() {
+        assert_eq!(
+            kinds("git log -n 3 'a b'"),
+            vec![
+                (0, 3, Kind::Command),
+                (4, 7, Kind::Command),
+                (8, 10, Kind::Flag),
+                (11, 12, Kind::Argument),
+                (13, 18, Kind::Quoted),
+            ]
+        );
+        assert_eq!(
+            kinds("nope x"),
+            vec![(0, 4, Kind::UnknownCommand), (5, 6, Kind::Argument)]
+        );
+        assert_eq!(
+            kinds("echo -- -x"),
+            vec![
+                (0, 4, Kind::Command),
+                (5, 7, Kind::Flag),
+                (8, 10, Kind::Argument),
+            ]
+        );
+        assert!(kinds("").is_empty());
+    }
+
+    #[test]
+    fn spans_of_special_commands() {
+        assert_eq!(kinds("!ls -la"), vec![(0, 1, Kind::Special)]);
+        assert_eq!(kinds("?ls"), vec![(0, 1, Kind::UnknownCommand)]);
+    }
+
+    #[test]
+    fn spans_of_unfinished_input() {
+        // A quote opened at the start of a word.
+        assert_eq!(
+            kinds("echo \"a b"),
+            vec![(0, 4, Kind::Command), (5, 9, Kind::Quoted)]
+        );
+        // A quote opened in the middle of a word covers the whole word.
+        assert_eq!(
+            kinds("echo ab'c d"),
+            vec![(0, 4, Kind::Command), (5, 11, Kind::Quoted)]
+        );
+        // The word ending with an escape is left out.
+        assert_eq!(kinds("x é\\"), vec![(0, 1, Kind::UnknownCommand)]);
+    }
+
+    #[test]
+    fn line_spans_are_cut_to_the_line() {
+        let all = vec![
+            Span {
+                start: 0,
+                end: 4,
+                kind: Kind::Command,
+            },
+            Span {
+                start: 5,
+                end: 12,
+                kind: Kind::Quoted,
+            },
+        ];
+        let line: Vec<(usize, usize, Kind)> = line_spans(&all, 8, 14)
+            .into_iter()
+            .map(|span| (span.start, span.end, span.kind))
+            .collect();
+        assert_eq!(line, vec![(0, 4, Kind::Quoted)]);
+    }
+
+    #[test]
+    fn paint_skips_overlapping_and_split_ranges() {
+        let bold = Style::new().bold();
+        let red = Style::new().fg(Color::Red);
+        let base = Style::new();
+        let painted = |text: &str, style: Style| format!("{}{}{}", style, text, style::Reset);
+
+        assert_eq!(
+            paint("abcd", vec![(2..3, red), (0..1, bold)], &base),
+            format!("{}b{}d", painted("a", bold), painted("c", red))
+        );
+        // The second range overlaps the first and is dropped.
+        assert_eq!(
+            paint("abcd", vec![(0..2, bold), (1..3, red)], &base),
+            format!("{}cd", painted("ab", bold))
+        );
+        // Ranges splitting `é` or past the end are dropped.
+        assert_eq!(paint("é!", vec![(0..1, bold), (1..9, red)], &base), "é!");
+        assert_eq!(
+            paint("ab", vec![(0..1, bold)], &red),
+            format!("{}{}{}b{}", red, painted("a", bold), red, style::Reset)
+        );
+    }
+}
@@ -0,0 +1,367 @@
+use serde::Deserialize;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// Permissions of the history file, which can hold passwords typed as
+/// arguments.
+const PRIVATE: u32 = 0o600;
+
+/// Which repeated entries are dropped when a line is added to the history.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dedup {
+    /// Keep every entry.
+    None,
+    /// Drop a line identical to the previous entry.
+    #[default]
+    Consecutive,
+    /// Drop earlier entries identical to the line, keeping the latest.
+    All,
+}
+
+/// Lines previously entered, optionally persisted to a file that is updated
+/// as each line is added.
+pub struct History {
+    entries: Vec<String>,
+    path: Option<PathBuf>,
+    max_size: usize,
+    dedup: Dedup,
+    ignore_space: bool,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            entries: Vec::new(),
+            path: None,
+            max_size: 1000,
+            dedup: Dedup::default(),
+            ignore_space: true,
+        }
+    }
+
+    /// Loads entries from `path`, which is created if missing, and saves
+    /// entries added from now on to it.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .mode(PRIVATE)
+            .open(&path)?;
+
+        self.entries.clear();
+        for line in BufReader::new(file).lines() {
+            let line = unescape(&line?);
+            if self.dedup == Dedup::All {
+                self.entries.retain(|e| *e != line);
+            }
+            self.entries.push(line);
+        }
+
+        self.path = Some(path);
+        if self.trim() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Sets the number of entries kept, dropping the oldest beyond it from
+    /// the history and its file straight away.
+    pub fn set_max_size(&mut self, max_size: usize) -> io::Result<()> {
+        self.max_size = max_size;
+        if self.trim() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    pub fn set_dedup(&mut self, dedup: Dedup) {
+        self.dedup = dedup;
+    }
+
+    /// Sets whether lines starting with a space are left out of the history.
+    pub fn set_ignore_space(&mut self, ignore_space: bool) {
+        self.ignore_space = ignore_space;
+    }
+
+    /// The file the history is saved to, once loaded.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Finds the closest entry before `from`, or after it unless `older` is
+    /// set, that starts with `prefix` and differs from `current`.
+    pub fn search_prefix(
+        &self,
+        prefix: &str,
+        from: usize,
+        older: bool,
+        current: &str,
+    ) -> Option<usize> {
+        let matches = |&i: &usize| {
+            let entry = &self.entries[i];
+            entry.starts_with(prefix) && entry != current
+        };
+        if older {
+            (0..from.min(self.entries.len())).rev().find(matches)
+        } else {
+            (from + 1..self.entries.len()).find(matches)
+        }
+    }
+
+    /// Adds a line, writing it to the history file straight away.
+    pub fn add<S: AsRef<str>>(&mut self, line: S) -> io::Result<()> {
+        let line = line.as_ref();
+        if (self.ignore_space && line.starts_with(' ')) || line.trim().is_empty() {
+            return Ok(());
+        }
+        let line = line.trim().to_string();
+
+        let mut rewrite = false;
+        match self.dedup {
+            Dedup::None => {}
+            Dedup::Consecutive => {
+                if self.entries.last() == Some(&line) {
+                    return Ok(());
+                }
+            }
+            Dedup::All => {
+                let len = self.entries.len();
+                self.entries.retain(|e| *e != line);
+                rewrite = len != self.entries.len();
+            }
+        }
+
+        self.entries.push(line);
+        rewrite |= self.trim();
+
+        if rewrite {
+            self.save()
+        } else {
+            self.append()
+        }
+    }
+
+    fn trim(&mut self) -> bool {
+        if self.entries.len() <= self.max_size {
+            return false;
+        }
+        let excess = self.entries.len() - self.max_size;
+        self.entries.drain(..excess);
+        true
+    }
+
+    fn append(&self) -> io::Result<()> {
+        let (path, line) = match (&self.path, self.entries.last()) {
+            (Some(path), Some(line)) => (path, line),
+            _ => return Ok(()),
+        };
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(PRIVATE)
+            .open(path)?;
+        writeln!(file, "{}", escape(line))?;
+        file.flush()
+    }
+
+    /// Rewrites the whole history file, replacing it atomically.
+    fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut temp = path.clone().into_os_string();
+        temp.push(".tmp");
+
+        // A file left by an earlier crash would keep its permissions.
+        let _ = fs::remove_file(&temp);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(PRIVATE)
+            .open(&temp)?;
+        for entry in self.entries.iter() {
+            writeln!(file, "{}", escape(entry))?;
+        }
+        file.sync_all()?;
+        fs::rename(&temp, path)
+    }
+}
+
+/// Escapes backslashes and newlines so that every entry is one line.
+fn escape(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(line: &str) -> String {
+    let mut entry = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                entry.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                entry.push('\\');
+                chars.next();
+            }
+            (c, _) => entry.push(c),
+        }
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_round_trip_through_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history");
+
+        let mut history = History::new();
+        history.load(&path).unwrap();
+        history.add("select 1").unwrap();
+        history.add("two\nlines").unwrap();
+        history.add(r"literal \n and \\").unwrap();
+
+        let mut loaded = History::new();
+        loaded.load(&path).unwrap();
+        assert_eq!(
+            loaded.entries(),
+            ["select 1", "two\nlines", r"literal \n and \\"]
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+    }
+
+    #[test]
+    fn dedup_drops_repeated_lines() {
+        let mut history = History::new();
+        for line in &["a", "a", "b", "a"] {
+            history.add(line).unwrap();
+        }
+        assert_eq!(history.entries(), ["a", "b", "a"]);
+
+        let mut history = History::new();
+        history.set_dedup(Dedup::All);
+        for line in &["a", "b", "a", "c", "b"] {
+            history.add(line).unwrap();
+        }
+        assert_eq!(history.entries(), ["a", "c", "b"]);
+
+        let mut history = History::new();
+        history.set_dedup(Dedup::None);
+        for line in &["a", "a"] {
+            history.add(line).unwrap();
+        }
+        assert_eq!(history.entries(), ["a", "a"]);
+    }
+
+    #[test]
+    fn blank_and_space_prefixed_lines_are_skipped() {
+        let mut history = History::new();
+        history.add("  ").unwrap();
+        history.add(" secret").unwrap();
+        history.set_ignore_space(false);
+        history.add(" kept ").unwrap();
+        assert_eq!(history.entries(), ["kept"]);
+    }
+
+    #[test]
+    fn max_size_trims_oldest_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history");
+
+        let mut history = History::new();
+        history.load(&path).unwrap();
+        history.set_max_size(3).unwrap();
+        for line in &["1", "2", "3", "4"] {
+            history.add(line).unwrap();
+        }
+        assert_eq!(history.entries(), ["2", "3", "4"]);
+
+        history.set_max_size(2).unwrap();
+        assert_eq!(history.entries(), ["3", "4"]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "3\n4\n");
+
+        let mut loaded = History::new();
+        loaded.set_max_size(1).unwrap();
+        loaded.load(&path).unwrap();
+        assert_eq!(loaded.entries(), ["4"]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "4\n");
+    }
+
+    #[test]
+    fn files_are_only_readable_by_the_user() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history");
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        let mut history = History::new();
+        history.load(&path).unwrap();
+        assert_eq!(mode(&path), 0o600);
+
+        fs::remove_file(&path).unwrap();
+        history.add("appended").unwrap();
+        assert_eq!(mode(&path), 0o600);
+
+        history.set_dedup(Dedup::All);
+        history.add("other").unwrap();
+        history.add("appended").unwrap();
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "other\nappended\n");
+    }
+
+    #[test]
+    fn search_prefix_skips_the_current_line() {
+        let mut history = History::new();
+        history.set_dedup(Dedup::None);
+        for line in &["ls", "git status", "git log", "git log", "pwd"] {
+            history.add(line).unwrap();
+        }
+        let len = history.len();
+
+        assert_eq!(history.search_prefix("git", len, true, "git"), Some(3));
+        assert_eq!(history.search_prefix("git", 3, true, "git log"), Some(1));
+        assert_eq!(history.search_prefix("git", 1, true, "git status"), None);
+        assert_eq!(
+            history.search_prefix("git", 1, false, "git status"),
+            Some(2)
+        );
+        assert_eq!(history.search_prefix("git", 3, false, "git log"), None);
+        assert_eq!(history.search_prefix("", len, true, ""), Some(4));
+        assert_eq!(history.search_prefix("x", len, true, ""), None);
+        assert_eq!(history.search_prefix("ls", len + 5, true, ""), Some(0));
+    }
+}
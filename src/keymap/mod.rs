@@ -0,0 +1,304 @@
+use crate::buffer::LineBuffer;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::rc::Rc;
+use termion::event::Key;
+
+/// A built-in editor action that keys can be bound to. In configuration
+/// files actions are named in kebab-case, like `kill-previous-word`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    /// Submits the line, or starts a continuation line when it is incomplete.
+    AcceptLine,
+    /// Selects the next suggestion.
+    NextSuggestion,
+    /// Selects the previous suggestion.
+    PreviousSuggestion,
+    /// Hides the suggestions.
+    HideSuggestions,
+    BackwardDeleteChar,
+    DeleteChar,
+    /// Deletes the grapheme under the cursor, or ends input on an empty line.
+    DeleteCharOrEof,
+    BackwardChar,
+    ForwardChar,
+    BackwardWord,
+    ForwardWord,
+    LineStart,
+    LineEnd,
+    /// Moves up a line of a multi-line input, or to the previous history
+    /// entry starting with what was typed.
+    PreviousHistory,
+    /// Moves down a line of a multi-line input, or to the next history entry
+    /// starting with what was typed.
+    NextHistory,
+    ReverseSearchHistory,
+    ForwardSearchHistory,
+    KillToStart,
+    KillToEnd,
+    KillPreviousWord,
+    KillNextWord,
+    Yank,
+    /// Replaces the text just yanked with the kill before it.
+    YankPop,
+    TransposeChars,
+    UpcaseWord,
+    DowncaseWord,
+    CapitalizeWord,
+    /// Restores the line as it was before the last edit. Characters typed
+    /// in a row are undone together.
+    Undo,
+    /// Reapplies the last edit undone.
+    Redo,
+    /// Opens the line in `$VISUAL` or `$EDITOR` and loads it back once the
+    /// editor exits.
+    EditInEditor,
+    ClearScreen,
+}
+
+/// Parses a key sequence written as space-separated keys, like
+/// `ctrl-x ctrl-e`, `alt-f`, `f1`, `tab` or `a`.
+pub fn parse_keys(keys: &str) -> Result<Vec<Key>, String> {
+    let keys: Result<Vec<Key>, String> = keys.split_whitespace().map(parse_key).collect();
+    match keys {
+        Ok(keys) if keys.is_empty() => Err("empty key sequence".to_string()),
+        keys => keys,
+    }
+}
+
+fn parse_key(key: &str) -> Result<Key, String> {
+    let lower = key.to_lowercase();
+    // Modifiers apply to a single character, which keeps its case after
+    // `alt-` as Alt-B and Alt-b are different keys.
+    let single = |name: &str| {
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    };
+    if let Some(c) = lower.strip_prefix("ctrl-").and_then(single) {
+        return Ok(Key::Ctrl(c));
+    }
+    if lower.starts_with("alt-") {
+        if let Some(c) = single(&key["alt-".len()..]) {
+            return Ok(Key::Alt(c));
+        }
+    }
+    if let Some(Ok(number)) = lower.strip_prefix('f').map(str::parse::<u8>) {
+        return Ok(Key::F(number));
+    }
+    let key = match lower.as_str() {
+        "enter" | "return" => Key::Char('\n'),
+        "tab" => Key::Char('\t'),
+        "space" => Key::Char(' '),
+        "backtab" | "shift-tab" => Key::BackTab,
+        "esc" | "escape" => Key::Esc,
+        "backspace" => Key::Backspace,
+        "delete" | "del" => Key::Delete,
+        "insert" => Key::Insert,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        _ => match single(key) {
+            Some(c) => Key::Char(c),
+            None => return Err(format!("unknown key `{}`", key)),
+        },
+    };
+    Ok(key)
+}
+
+/// What a custom binding asks the terminal to do once it has run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CallbackResult {
+    /// Keeps editing, redrawing the line.
+    Continue,
+    /// Submits the line.
+    Accept,
+    /// Runs the given line as a command, then keeps editing.
+    Execute(String),
+}
+
+pub type Callback = Rc<dyn Fn(&mut LineBuffer) -> CallbackResult>;
+
+#[derive(Clone)]
+pub enum Binding {
+    Action(Action),
+    Callback(Callback),
+}
+
+/// Result of looking up the keys typed so far.
+pub enum Lookup<'a> {
+    /// No binding starts with the keys.
+    None,
+    /// The keys start a longer binding, so more keys are needed.
+    Prefix,
+    Found(&'a Binding),
+}
+
+/// Bindings from key sequences to editor actions or callbacks. A sequence
+/// of several keys, like Ctrl-X Ctrl-E, is a chord: its keys are collected
+/// until they match a binding or no binding starts with them. Characters
+/// without a binding of their own are inserted.
+#[derive(Clone, Default)]
+pub struct Keymap {
+    bindings: HashMap<Vec<Key>, Binding>,
+}
+
+impl Keymap {
+    /// A keymap without any bindings.
+    pub fn new() -> Keymap {
+        Keymap::default()
+    }
+
+    /// The readline Emacs bindings.
+    pub fn emacs() -> Keymap {
+        let mut keymap = Keymap::new();
+        let bindings = [
+            (Key::Char('\n'), Action::AcceptLine),
+            (Key::Char('\t'), Action::NextSuggestion),
+            (Key::BackTab, Action::PreviousSuggestion),
+            (Key::Esc, Action::HideSuggestions),
+            (Key::Backspace, Action::BackwardDeleteChar),
+            (Key::Delete, Action::DeleteChar),
+            (Key::Ctrl('d'), Action::DeleteCharOrEof),
+            (Key::Left, Action::BackwardChar),
+            (Key::Ctrl('b'), Action::BackwardChar),
+            (Key::Right, Action::ForwardChar),
+            (Key::Ctrl('f'), Action::ForwardChar),
+            (Key::Alt('b'), Action::BackwardWord),
+            (Key::Alt('f'), Action::ForwardWord),
+            (Key::Home, Action::LineStart),
+            (Key::Ctrl('a'), Action::LineStart),
+            (Key::End, Action::LineEnd),
+            (Key::Ctrl('e'), Action::LineEnd),
+            (Key::Up, Action::PreviousHistory),
+            (Key::Down, Action::NextHistory),
+            (Key::Ctrl('r'), Action::ReverseSearchHistory),
+            (Key::Ctrl('s'), Action::ForwardSearchHistory),
+            (Key::Ctrl('u'), Action::KillToStart),
+            (Key::Ctrl('k'), Action::KillToEnd),
+            (Key::Ctrl('w'), Action::KillPreviousWord),
+            (Key::Alt('d'), Action::KillNextWord),
+            (Key::Ctrl('y'), Action::Yank),
+            (Key::Alt('y'), Action::YankPop),
+            (Key::Ctrl('t'), Action::TransposeChars),
+            (Key::Alt('u'), Action::UpcaseWord),
+            (Key::Alt('l'), Action::DowncaseWord),
+            (Key::Alt('c'), Action::CapitalizeWord),
+            (Key::Ctrl('7'), Action::Undo),
+            (Key::Ctrl('z'), Action::Undo),
+            (Key::Alt('_'), Action::Redo),
+            (Key::Ctrl('l'), Action::ClearScreen),
+        ];
+        for (key, action) in bindings.iter() {
+            keymap.bind(&[*key], *action);
+        }
+        keymap.bind(&[Key::Ctrl('x'), Key::Ctrl('e')], Action::EditInEditor);
+        keymap
+    }
+
+    /// Binds a key sequence to a built-in action.
+    pub fn bind(&mut self, keys: &[Key], action: Action) {
+        self.bindings.insert(keys.to_vec(), Binding::Action(action));
+    }
+
+    /// Binds a key sequence to a callback, which can change the line being
+    /// edited and tells the terminal what to do next.
+    pub fn bind_callback<F>(&mut self, keys: &[Key], f: F)
+    where
+        F: Fn(&mut LineBuffer) -> CallbackResult + 'static,
+    {
+        self.bindings
+            .insert(keys.to_vec(), Binding::Callback(Rc::new(f)));
+    }
+
+    pub fn unbind(&mut self, keys: &[Key]) {
+        self.bindings.remove(keys);
+    }
+
+    pub fn lookup(&self, keys: &[Key]) -> Lookup<'_> {
+        if let Some(binding) = self.bindings.get(keys) {
+            return Lookup::Found(binding);
+        }
+        let is_prefix = self
+            .bindings
+            .keys()
+            .any(|bound| bound.len() > keys.len() && bound.starts_with(keys));
+        if is_prefix {
+            Lookup::Prefix
+        } else {
+            Lookup::None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_keys_reads_names_and_modifiers() {
+        assert_eq!(
+            parse_keys("ctrl-x ctrl-e"),
+            Ok(vec![Key::Ctrl('x'), Key::Ctrl('e')])
+        );
+        assert_eq!(parse_keys("Ctrl-A"), Ok(vec![Key::Ctrl('a')]));
+        assert_eq!(
+            parse_keys("alt-b alt-B"),
+            Ok(vec![Key::Alt('b'), Key::Alt('B')])
+        );
+        assert_eq!(
+            parse_keys("f1 F12 tab shift-tab enter space esc"),
+            Ok(vec![
+                Key::F(1),
+                Key::F(12),
+                Key::Char('\t'),
+                Key::BackTab,
+                Key::Char('\n'),
+                Key::Char(' '),
+                Key::Esc,
+            ])
+        );
+        assert_eq!(parse_keys("a é"), Ok(vec![Key::Char('a'), Key::Char('é')]));
+    }
+
+    #[test]
+    fn parse_keys_rejects_unknown_keys() {
+        assert!(parse_keys("").is_err());
+        assert!(parse_keys("   ").is_err());
+        assert!(parse_keys("ctrl-xx").is_err());
+        assert!(parse_keys("ctrl-x hyper-q").is_err());
+    }
+
+    #[test]
+    fn lookup_follows_chords() {
+        let mut keymap = Keymap::emacs();
+        keymap.bind(&[Key::Ctrl('x'), Key::Char('p')], Action::Yank);
+
+        assert!(matches!(keymap.lookup(&[Key::Ctrl('x')]), Lookup::Prefix));
+        assert!(matches!(
+            keymap.lookup(&[Key::Ctrl('x'), Key::Ctrl('e')]),
+            Lookup::Found(Binding::Action(Action::EditInEditor))
+        ));
+        assert!(matches!(
+            keymap.lookup(&[Key::Ctrl('x'), Key::Char('p')]),
+            Lookup::Found(Binding::Action(Action::Yank))
+        ));
+        assert!(matches!(
+            keymap.lookup(&[Key::Ctrl('x'), Key::Char('q')]),
+            Lookup::None
+        ));
+        assert!(matches!(keymap.lookup(&[Key::Char('q')]), Lookup::None));
+
+        keymap.unbind(&[Key::Ctrl('x'), Key::Ctrl('e')]);
+        keymap.unbind(&[Key::Ctrl('x'), Key::Char('p')]);
+        assert!(matches!(keymap.lookup(&[Key::Ctrl('x')]), Lookup::None));
+    }
+}
@@ -1,3 +1,12 @@
 pub mod arguments;
+pub mod buffer;
 pub mod command;
+pub mod completion;
+pub mod config;
+pub mod highlight;
+pub mod history;
+pub mod keymap;
+pub mod prompt;
 pub mod terminal;
+pub mod theme;
+pub mod vi;
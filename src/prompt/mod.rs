@@ -0,0 +1,257 @@
+use crate::theme::Theme;
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+use termion::style;
+
+/// How the last command run from the prompt went.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Success,
+    Failure,
+}
+
+/// What a prompt is rendered from, updated by the terminal as lines are run.
+#[derive(Clone, Debug, Default)]
+pub struct Context {
+    /// Status of the last command, or `None` before the first one.
+    pub status: Option<Status>,
+    /// Name set with `Terminal::set_context_name`, like the environment or
+    /// server the application is connected to.
+    pub name: Option<String>,
+    /// How long the last command took to run.
+    pub duration: Option<Duration>,
+}
+
+pub type Renderer = Rc<dyn Fn(&Context) -> Option<String>>;
+
+#[derive(Clone)]
+enum Kind {
+    Text(String),
+    Cwd,
+    GitBranch,
+    Status { success: String, failure: String },
+    Time,
+    Duration,
+    Name,
+    Custom(Renderer),
+}
+
+/// A part of a prompt. Segments with nothing to show, like the git branch
+/// outside of a repository, are left out along with their decoration.
+#[derive(Clone)]
+pub struct Segment {
+    kind: Kind,
+    style: Option<String>,
+    before: String,
+    after: String,
+}
+
+impl Segment {
+    fn new(kind: Kind) -> Segment {
+        Segment {
+            kind,
+            style: None,
+            before: "".to_string(),
+            after: "".to_string(),
+        }
+    }
+
+    pub fn text<S: AsRef<str>>(text: S) -> Segment {
+        Segment::new(Kind::Text(text.as_ref().to_string()))
+    }
+
+    /// The working directory, with the home directory shown as `~`.
+    pub fn cwd() -> Segment {
+        Segment::new(Kind::Cwd)
+    }
+
+    /// The branch checked out in the git repository containing the working
+    /// directory, or the start of the commit when the head is detached.
+    pub fn git_branch() -> Segment {
+        Segment::new(Kind::GitBranch)
+    }
+
+    /// `success` or `failure` depending on how the last command went.
+    pub fn status<S: AsRef<str>>(success: S, failure: S) -> Segment {
+        Segment::new(Kind::Status {
+            success: success.as_ref().to_string(),
+            failure: failure.as_ref().to_string(),
+        })
+    }
+
+    /// The local time, as hours, minutes and seconds.
+    pub fn time() -> Segment {
+        Segment::new(Kind::Time)
+    }
+
+    /// How long the last command took, like `350ms`, `2.5s` or `1m20s`.
+    pub fn duration() -> Segment {
+        Segment::new(Kind::Duration)
+    }
+
+    /// The context name set on the terminal.
+    pub fn name() -> Segment {
+        Segment::new(Kind::Name)
+    }
+
+    /// A segment rendered by a callback, which returns `None` to leave it
+    /// out.
+    pub fn custom<F>(f: F) -> Segment
+    where
+        F: Fn(&Context) -> Option<String> + 'static,
+    {
+        Segment::new(Kind::Custom(Rc::new(f)))
+    }
+
+    /// Style written before the segment, like `termion::color::Fg(Blue)`,
+    /// and reset after it. It is left out when the theme has no colors.
+    pub fn style<D: Display>(mut self, style: D) -> Segment {
+        self.style = Some(style.to_string());
+        self
+    }
+
+    /// Text written around the segment when it is shown, outside of its
+    /// style.
+    pub fn around<S: AsRef<str>>(mut self, before: S, after: S) -> Segment {
+        self.before = before.as_ref().to_string();
+        self.after = after.as_ref().to_string();
+        self
+    }
+
+    fn value(&self, context: &Context) -> Option<String> {
+        match &self.kind {
+            Kind::Text(text) => Some(text.clone()),
+            Kind::Cwd => cwd(),
+            Kind::GitBranch => git_branch(),
+            Kind::Status { success, failure } => match context.status? {
+                Status::Success => Some(success.clone()),
+                Status::Failure => Some(failure.clone()),
+            },
+            Kind::Time => Some(time()),
+            Kind::Duration => context.duration.map(format_duration),
+            Kind::Name => context.name.clone(),
+            Kind::Custom(f) => f(context),
+        }
+    }
+
+    /// The segment as written over the theme's prompt style, which is
+    /// restored after the segment's own.
+    fn render(&self, context: &Context, theme: &Theme) -> Option<String> {
+        let value = self.value(context)?;
+        Some(match &self.style {
+            Some(style) if theme.colors => format!(
+                "{}{}{}{}{}{}",
+                self.before,
+                style,
+                value,
+                style::Reset,
+                theme.prompt,
+                self.after
+            ),
+            _ => format!("{}{}{}", self.before, value, self.after),
+        })
+    }
+}
+
+/// A prompt made of segments, rendered again before each line is read.
+#[derive(Clone, Default)]
+pub struct Prompt {
+    segments: Vec<Segment>,
+}
+
+impl Prompt {
+    pub fn new() -> Prompt {
+        Prompt::default()
+    }
+
+    pub fn segment(mut self, segment: Segment) -> Prompt {
+        self.segments.push(segment);
+        self
+    }
+
+    /// Renders the segments, with text they don't style themselves in the
+    /// theme's prompt style.
+    pub fn render(&self, context: &Context, theme: &Theme) -> String {
+        let segments: String = self
+            .segments
+            .iter()
+            .filter_map(|segment| segment.render(context, theme))
+            .collect();
+        theme.prompt.paint(segments)
+    }
+}
+
+impl From<&str> for Prompt {
+    fn from(text: &str) -> Prompt {
+        Prompt::new().segment(Segment::text(text))
+    }
+}
+
+fn cwd() -> Option<String> {
+    let cwd = env::current_dir().ok()?;
+    if let Some(home) = env::var_os("HOME").map(PathBuf::from) {
+        if let Ok(rest) = cwd.strip_prefix(&home) {
+            if rest.as_os_str().is_empty() {
+                return Some("~".to_string());
+            }
+            return Some(format!("~/{}", rest.display()));
+        }
+    }
+    Some(cwd.display().to_string())
+}
+
+/// The git directory of the repository containing `dir`. In worktrees and
+/// submodules `.git` is a file naming the git directory.
+fn git_dir(dir: &Path) -> Option<PathBuf> {
+    for dir in dir.ancestors() {
+        let git = dir.join(".git");
+        if git.is_dir() {
+            return Some(git);
+        }
+        if let Ok(contents) = fs::read_to_string(&git) {
+            let path = contents.strip_prefix("gitdir:")?.trim();
+            return Some(dir.join(path));
+        }
+    }
+    None
+}
+
+fn git_branch() -> Option<String> {
+    let git_dir = git_dir(&env::current_dir().ok()?)?;
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref: ") {
+        Some(reference) => Some(
+            reference
+                .strip_prefix("refs/heads/")
+                .unwrap_or(reference)
+                .to_string(),
+        ),
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    if millis < 1000 {
+        format!("{}ms", millis)
+    } else if millis < 60_000 {
+        format!("{:.1}s", duration.as_secs_f64())
+    } else {
+        format!("{}m{}s", millis / 60_000, millis % 60_000 / 1000)
+    }
+}
+
+fn time() -> String {
+    let tm = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        tm
+    };
+    format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)
+}
@@ -1,27 +1,156 @@
 extern crate termion;
 
-use crate::{arguments, command};
+use crate::{
+    arguments, buffer, command, completion, config, highlight, history, keymap, prompt, theme, vi,
+};
+use serde::Deserialize;
 use std::collections::HashMap;
-use std::io::{stdin, stdout, Stdin, Stdout, Write};
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, stdout, Read, Stdout, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use termion::{
-    clear, color,
-    cursor::{self, DetectCursorPos},
+    clear, cursor,
     event::Key,
     input::TermRead,
     raw::{IntoRawMode, RawTerminal},
+    style,
 };
 
+/// How an incremental history search ended.
+enum SearchEnd {
+    /// Enter was pressed, submitting the found line.
+    Accept,
+    /// The found line was kept for editing, and the key that ended the
+    /// search is handled as usual.
+    Edit(Key),
+    /// The search was abandoned, restoring the line as it was.
+    Cancel,
+}
+
+/// Key bindings used to edit the line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EditMode {
+    /// The readline Emacs bindings.
+    #[default]
+    Emacs,
+    /// Vi insert and normal modes, with the mode shown before the prompt.
+    Vi,
+}
+
+/// What `read_chars` does once a key binding has run.
+enum Flow {
+    Continue,
+    /// The line was submitted.
+    Accept,
+    /// Reading stops without a line.
+    Exit,
+}
+
+/// The last edit made to the line, for the edits that depend on it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum LastEdit {
+    #[default]
+    Other,
+    /// Consecutive kills join into one kill ring entry.
+    Kill,
+    /// Text was yanked at the given offset, which Alt-Y can replace.
+    Yank(usize),
+    /// A character was typed.
+    Insert,
+    /// An edit was undone or redone.
+    Undo,
+}
+
+/// State kept between the keys of the line being read.
+#[derive(Default)]
+struct ReadState {
+    history_index: usize,
+    history_prefix: String,
+    last_edit: LastEdit,
+    /// Keys typed so far of a binding made of several keys.
+    chord: Vec<Key>,
+}
+
+/// How long to wait for a key before checking whether the terminal has been
+/// resized, in milliseconds.
+const RESIZE_POLL_MS: i32 = 100;
+
+/// Standard input read a byte at a time. Nothing is buffered beyond the key
+/// being parsed, so polling the descriptor tells whether a key is waiting.
+/// termion tells a lone Escape from an escape sequence by whether the next
+/// byte was read along with it, so a byte waiting after Escape is read too.
+struct UnbufferedStdin;
+
+impl UnbufferedStdin {
+    /// Waits up to `timeout` milliseconds for input, returning whether there
+    /// is some to read.
+    fn wait(timeout: i32) -> bool {
+        let mut fds = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        unsafe { libc::poll(&mut fds, 1, timeout) > 0 }
+    }
+
+    fn read_byte(byte: &mut u8) -> io::Result<usize> {
+        let read =
+            unsafe { libc::read(libc::STDIN_FILENO, byte as *mut u8 as *mut libc::c_void, 1) };
+        if read < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(read as usize)
+    }
+}
+
+impl Read for UnbufferedStdin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let read = UnbufferedStdin::read_byte(&mut buf[0])?;
+        if read == 1 && buf[0] == b'\x1B' && buf.len() > 1 && UnbufferedStdin::wait(0) {
+            return Ok(1 + UnbufferedStdin::read_byte(&mut buf[1])?);
+        }
+        Ok(read)
+    }
+}
+
 pub struct Terminal {
     stdout: RawTerminal<Stdout>,
-    stdin: Stdin,
+    prompt: prompt::Prompt,
+    prompt_context: prompt::Context,
+    /// The prompt as rendered for the line being read.
     prefix: String,
-    current_input: String,
-    previous_input: Vec<String>,
+    right_prompt: prompt::Prompt,
+    right_prefix: String,
+    /// Whether the right prompt was drawn with the line.
+    right_prompt_shown: bool,
+    theme: theme::Theme,
+    highlighter: Option<Box<dyn highlight::Highlighter>>,
+    continuation_prefix: String,
+    buffer: buffer::LineBuffer,
+    kill_ring: buffer::KillRing,
+    undo: buffer::UndoStack,
+    edit_mode: EditMode,
+    vi: vi::Vi,
+    keymap: keymap::Keymap,
+    /// Row of the cursor, counted from the prompt's first row.
+    cursor_row: usize,
+    history: history::History,
     suggestion_selection: usize,
+    suggestions_visible: bool,
+    match_mode: completion::MatchMode,
 
-    commands: HashMap<String, command::Command>,
+    commands: command::CommandTree,
     special_commands: HashMap<char, command::SpecialCommand>,
-    suggestions: Vec<(String, String)>,
+    special_suggestions: Vec<(String, String)>,
 }
 
 impl Default for Terminal {
@@ -35,98 +164,328 @@ impl Terminal {
         let stdout = stdout();
         let stdout = stdout.into_raw_mode().unwrap();
 
-        let stdin = stdin();
-
         Terminal {
             stdout,
-            stdin,
-            prefix: ">>> ".to_string(),
-            current_input: "".to_string(),
-            previous_input: Vec::new(),
+            prompt: prompt::Prompt::from(">>> "),
+            prompt_context: prompt::Context::default(),
+            prefix: "".to_string(),
+            right_prompt: prompt::Prompt::new(),
+            right_prefix: "".to_string(),
+            right_prompt_shown: false,
+            theme: theme::Theme::default(),
+            highlighter: None,
+            continuation_prefix: "... ".to_string(),
+            buffer: buffer::LineBuffer::new(),
+            kill_ring: buffer::KillRing::new(),
+            undo: buffer::UndoStack::new(),
+            edit_mode: EditMode::default(),
+            vi: vi::Vi::new(),
+            keymap: keymap::Keymap::emacs(),
+            cursor_row: 0,
+            history: history::History::new(),
             suggestion_selection: 0,
-            commands: HashMap::new(),
+            suggestions_visible: false,
+            match_mode: completion::MatchMode::default(),
+            commands: command::CommandTree::new(),
             special_commands: HashMap::new(),
-            suggestions: Vec::new(),
+            special_suggestions: Vec::new(),
         }
     }
 
     pub fn run_loop(&mut self) {
         loop {
             self.write_prefix();
-            let line = self.read_chars();
-
-            let args = arguments::parse_arguments(&line);
-            let args = if let Some(a) = args {
-                a
-            } else {
-                continue;
+            let line = match self.read_chars() {
+                Some(line) => line,
+                None => break,
             };
+            if !self.run_line(&line) {
+                break;
+            }
+        }
 
-            let result: Option<command::CommandResult> = match args {
-                arguments::ArgumentResult::Command(cmd, args) => self.execute_command(cmd, args),
-                arguments::ArgumentResult::Special(cmd, args) => {
-                    self.execute_special_command(cmd, args)
-                }
-            };
+        self.new_line();
+    }
 
-            match result {
-                Some(output) => match output {
-                    command::CommandResult::Exit => break,
-                    command::CommandResult::Success(msg) => {
-                        print!("\r\n[SUCCESS] {}", msg)
-                    }
-                    command::CommandResult::Failure(msg) => {
-                        print!("\r\n[FAILURE] {}", msg)
-                    }
-                },
-                None => {
-                    self.write(format!("\r\n[FAILURE] {}: command not found", line));
+    /// Runs a line as a command and prints its result. Returns false when
+    /// the command asks to exit.
+    fn run_line(&mut self, line: &str) -> bool {
+        let args = match self.parse_line(line) {
+            Ok(a) => a,
+            Err(arguments::ParseError::Empty) => return true,
+            Err(e) => {
+                self.prompt_context.status = Some(prompt::Status::Failure);
+                self.prompt_context.duration = None;
+                self.write(format!("\r\n{}", self.failure(e)));
+                self.new_line();
+                return true;
+            }
+        };
+
+        let started = Instant::now();
+        let result: Option<command::CommandResult> = match args {
+            arguments::ArgumentResult::Command(cmd, positional, args) => {
+                self.execute_command(cmd, positional, args)
+            }
+            arguments::ArgumentResult::Special(cmd, args) => {
+                self.execute_special_command(cmd, args)
+            }
+        };
+        self.prompt_context.duration = Some(started.elapsed());
+
+        match result {
+            Some(output) => match output {
+                command::CommandResult::Exit => return false,
+                command::CommandResult::Success(msg) => {
+                    self.prompt_context.status = Some(prompt::Status::Success);
+                    print!("\r\n{}", self.success(msg))
+                }
+                command::CommandResult::Failure(msg) => {
+                    self.prompt_context.status = Some(prompt::Status::Failure);
+                    print!("\r\n{}", self.failure(msg))
                 }
+            },
+            None => {
+                self.prompt_context.status = Some(prompt::Status::Failure);
+                self.write(format!(
+                    "\r\n{}",
+                    self.failure(format!("{}: command not found", line))
+                ));
             }
-            self.new_line();
         }
-
         self.new_line();
+        true
+    }
+
+    /// Loads history from `path` and saves every accepted line to it.
+    pub fn set_history_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.history.load(path)
+    }
+
+    /// Gives access to the history, to change its size and dedup policy.
+    pub fn history_mut(&mut self) -> &mut history::History {
+        &mut self.history
+    }
+
+    /// Sets the prompt, which is rendered again before each line is read.
+    pub fn set_prompt(&mut self, prompt: prompt::Prompt) {
+        self.prompt = prompt;
+    }
+
+    /// Sets the prompt shown at the right end of the first row, which is
+    /// hidden while the input would come close to it.
+    pub fn set_right_prompt(&mut self, prompt: prompt::Prompt) {
+        self.right_prompt = prompt;
+    }
+
+    /// Applies settings loaded with `config::Config::load`. Settings left out
+    /// of the configuration are kept.
+    pub fn apply_config(&mut self, config: &config::Config) -> Result<(), config::ConfigError> {
+        if let Some(segments) = &config.prompt.segments {
+            self.prompt = config::prompt(segments);
+        }
+        if let Some(segments) = &config.prompt.right_segments {
+            self.right_prompt = config::prompt(segments);
+        }
+        if let Some(continuation) = &config.prompt.continuation {
+            self.continuation_prefix = continuation.clone();
+        }
+
+        config.theme.apply(&mut self.theme);
+
+        if let Some(mode) = config.keymap.mode {
+            self.edit_mode = mode;
+        }
+        let parse = |keys: &String| {
+            keymap::parse_keys(keys).map_err(|e| config::ConfigError::Keys(keys.clone(), e))
+        };
+        for keys in &config.keymap.unbind {
+            self.keymap.unbind(&parse(keys)?);
+        }
+        for (keys, action) in &config.keymap.bindings {
+            self.keymap.bind(&parse(keys)?, *action);
+        }
+
+        if let Some(mode) = config.completion.mode {
+            self.match_mode = mode;
+        }
+
+        if let Some(size) = config.history.size {
+            if let Err(e) = self.history.set_max_size(size) {
+                let path = self
+                    .history
+                    .path()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default();
+                return Err(config::ConfigError::Io(path, e));
+            }
+        }
+        if let Some(dedup) = config.history.dedup {
+            self.history.set_dedup(dedup);
+        }
+        if let Some(ignore_space) = config.history.ignore_space {
+            self.history.set_ignore_space(ignore_space);
+        }
+        if let Some(path) = config.history.path() {
+            self.history
+                .load(&path)
+                .map_err(|e| config::ConfigError::Io(path, e))?;
+        }
+        Ok(())
+    }
+
+    /// Sets the styles of the prompt, input, suggestion popup and messages.
+    /// The theme is replaced by `Theme::no_color()` when `NO_COLOR` is set.
+    pub fn set_theme(&mut self, theme: theme::Theme) {
+        self.theme = theme.or_no_color();
+    }
+
+    /// Colors the input as it is typed, like `highlight::Styles::default()`
+    /// does with command names, flags and quoted words.
+    pub fn set_highlighter<H: highlight::Highlighter + 'static>(&mut self, highlighter: H) {
+        self.highlighter = Some(Box::new(highlighter));
+    }
+
+    /// Sets a fixed prompt.
+    pub fn set_prefix<S: AsRef<str>>(&mut self, prefix: S) {
+        self.prompt = prompt::Prompt::from(prefix.as_ref());
+    }
+
+    /// Sets a callback rendering the prompt before each line is read.
+    pub fn set_prompt_callback<F>(&mut self, f: F)
+    where
+        F: Fn(&prompt::Context) -> String + 'static,
+    {
+        self.prompt = prompt::Prompt::new().segment(prompt::Segment::custom(move |c| Some(f(c))));
+    }
+
+    /// Sets the name shown by `prompt::Segment::name`, or hides it.
+    pub fn set_context_name(&mut self, name: Option<&str>) {
+        self.prompt_context.name = name.map(|n| n.to_string());
+    }
+
+    /// Sets the prefix shown before each continuation line of a multi-line
+    /// input.
+    pub fn set_continuation_prefix<S: AsRef<str>>(&mut self, prefix: S) {
+        self.continuation_prefix = prefix.as_ref().to_string();
+    }
+
+    /// Sets the key bindings used to edit the line.
+    pub fn set_edit_mode(&mut self, mode: EditMode) {
+        self.edit_mode = mode;
+    }
+
+    /// Gives access to the key bindings, to bind keys to editor actions or
+    /// custom callbacks.
+    pub fn keymap_mut(&mut self) -> &mut keymap::Keymap {
+        &mut self.keymap
     }
 
-    pub fn add_command(&mut self, name: &str, f: command::Command, description: &str) {
-        self.commands.insert(name.to_string(), f);
-        self.add_suggestion(name, description);
+    pub fn set_keymap(&mut self, keymap: keymap::Keymap) {
+        self.keymap = keymap;
     }
 
-    pub fn add_special_command(&mut self, c: char, f: command::SpecialCommand, description: &str) {
-        self.special_commands.insert(c, f);
-        self.add_suggestion(c.to_string(), description);
+    /// Sets how typed text is matched against suggestions.
+    pub fn set_match_mode(&mut self, mode: completion::MatchMode) {
+        self.match_mode = mode;
     }
 
-    fn add_suggestion<S: AsRef<str>>(&mut self, name: S, description: &str) {
-        self.suggestions
-            .push((name.as_ref().to_string(), description.to_string()));
-        self.suggestions.sort();
+    /// Registers a command. Names made of several words, like `db migrate`,
+    /// register a subcommand below the commands named by the leading words.
+    pub fn add_command<C>(&mut self, name: &str, c: C, description: &str)
+    where
+        C: command::Command + 'static,
+    {
+        let path: Vec<&str> = name.split_whitespace().collect();
+        self.commands.insert(&path, Box::new(c), description);
     }
 
+    /// Describes a group of subcommands that isn't a command itself.
+    pub fn add_command_group(&mut self, name: &str, description: &str) {
+        let path: Vec<&str> = name.split_whitespace().collect();
+        self.commands.describe(&path, description);
+    }
+
+    pub fn add_special_command<F>(&mut self, c: char, f: F, description: &str)
+    where
+        F: FnMut(String) -> command::CommandResult + 'static,
+    {
+        self.special_commands.insert(c, Box::new(f));
+        self.special_suggestions
+            .push((c.to_string(), description.to_string()));
+        self.special_suggestions.sort();
+    }
+
+    fn command_path(name: &str, positional: &[String]) -> Vec<String> {
+        let mut path = vec![name.to_string()];
+        path.extend(positional.iter().cloned());
+        path
+    }
+
+    /// Parses a line, taking the flags declared by the named command into
+    /// account.
+    fn parse_line(&self, line: &str) -> Result<arguments::ArgumentResult, arguments::ParseError> {
+        let result = arguments::parse_arguments(line)?;
+        if let arguments::ArgumentResult::Command(cmd, positional, _) = &result {
+            let (node, depth) = self.commands.find(&Terminal::command_path(cmd, positional));
+            if let (Some(command), true) = (node.command(), depth > 0) {
+                return arguments::parse_arguments_with_flags(line, &command.flags());
+            }
+        }
+        Ok(result)
+    }
+
+    /// Executes the command named by `name` and as many leading positional
+    /// arguments as name its subcommands.
     pub fn execute_command(
-        &self,
+        &mut self,
         name: String,
-        args: HashMap<String, arguments::Argument>,
+        positional: Vec<String>,
+        mut args: HashMap<String, arguments::Argument>,
     ) -> Option<command::CommandResult> {
-        if !self.commands.contains_key(&name) {
-            None
-        } else {
-            Some(self.commands[&name](args))
+        let (node, depth) = self
+            .commands
+            .find_mut(&Terminal::command_path(&name, &positional));
+        if depth == 0 {
+            return None;
+        }
+        let positional = positional[depth - 1..].to_vec();
+
+        let command = match node.command_mut() {
+            Some(command) => command,
+            None => {
+                let subcommands: Vec<&str> = node.children().map(|(k, _)| k.as_str()).collect();
+                return Some(command::CommandResult::Failure(format!(
+                    "expected a subcommand: {}",
+                    subcommands.join(", ")
+                )));
+            }
+        };
+
+        let flags = command.flags();
+        let positionals = command.positionals();
+        if flags.is_empty() && positionals.is_empty() {
+            if !positional.is_empty() {
+                args.insert(
+                    arguments::POSITIONAL.to_string(),
+                    arguments::Argument::List(positional),
+                );
+            }
+            return Some(command.execute(args));
+        }
+
+        match arguments::validate_arguments(&flags, &positionals, positional, args) {
+            Ok(args) => Some(command.execute(args)),
+            Err(e) => Some(command::CommandResult::Failure(e.to_string())),
         }
     }
 
     pub fn execute_special_command(
-        &self,
+        &mut self,
         name: char,
         args: String,
     ) -> Option<command::CommandResult> {
-        if !self.special_commands.contains_key(&name) {
-            None
-        } else {
-            Some(self.special_commands[&name](args))
-        }
+        self.special_commands.get_mut(&name).map(|f| f(args))
     }
 
     pub fn write<S: AsRef<str>>(&self, string: S) {
@@ -135,380 +494,992 @@ impl Terminal {
         lock.flush().unwrap();
     }
 
-    pub fn write_prefix(&self) {
-        let prefix = self.prefix.clone();
-        self.write(prefix);
+    /// The prefix as shown before the first line of input, which in vi mode
+    /// starts with the current mode.
+    fn prompt(&self) -> String {
+        match self.edit_mode {
+            EditMode::Emacs => self.prefix.clone(),
+            EditMode::Vi => format!(
+                "{}{}",
+                self.theme.hint.paint(self.vi.mode().indicator()),
+                self.prefix
+            ),
+        }
+    }
+
+    fn success<D: Display>(&self, message: D) -> String {
+        format!("{} {}", self.theme.success.paint("[SUCCESS]"), message)
+    }
+
+    fn failure<D: Display>(&self, message: D) -> String {
+        format!("{} {}", self.theme.failure.paint("[FAILURE]"), message)
+    }
+
+    /// Renders the prompts and writes them.
+    pub fn write_prefix(&mut self) {
+        self.prefix = self.prompt.render(&self.prompt_context, &self.theme);
+        self.right_prefix = self.right_prompt.render(&self.prompt_context, &self.theme);
+        let right = self.right_prompt(Terminal::columns());
+        self.right_prompt_shown = !right.is_empty();
+        self.write(format!("{}{}", right, self.prompt()));
+    }
+
+    /// The right prompt drawn at the end of the first row, returning to the
+    /// start of the row, or nothing when the first line of input wraps or
+    /// comes within a column of it. The last column is left empty so the
+    /// row never fills.
+    fn right_prompt(&self, columns: usize) -> String {
+        let width = buffer::width(&self.right_prefix);
+        if width == 0 {
+            return "".to_string();
+        }
+        let text = self.buffer.text();
+        let (row, column) = self.position_of(text.find('\n').unwrap_or(text.len()), columns);
+        if row > 0 || column + width + 2 > columns {
+            return "".to_string();
+        }
+        format!(
+            "\r{}{}\r",
+            Terminal::move_right(columns - width - 1),
+            self.right_prefix
+        )
     }
 
     pub fn new_line(&self) {
         self.write("\r\n");
     }
 
-    // fn read_line(&mut self) -> String {
-    //     let mut lock = self.stdin.lock();
-    //     let line = lock.read_line().unwrap();
-    //     line.unwrap()
-    // }
+    /// Cursor movements that write nothing for a distance of zero, which
+    /// terminals would otherwise treat as one.
+    fn move_right(columns: usize) -> String {
+        if columns == 0 {
+            return "".to_string();
+        }
+        cursor::Right(columns as u16).to_string()
+    }
 
-    fn get_cursor_position(&self) -> (u16, u16) {
-        let mut lock = self.stdout.lock();
-        lock.cursor_pos().unwrap()
+    fn move_up(rows: usize) -> String {
+        if rows == 0 {
+            return "".to_string();
+        }
+        cursor::Up(rows as u16).to_string()
     }
 
-    fn get_relative_cursor_position(&self) -> (u16, u16) {
-        let (x, y) = self.get_cursor_position();
-        ((x - self.prefix.len() as u16), y)
+    fn columns() -> usize {
+        termion::terminal_size()
+            .map_or(80, |(x, _)| x as usize)
+            .max(1)
     }
 
-    fn cursor_can_go_left(&self) -> bool {
-        let (x, _) = self.get_relative_cursor_position();
-        x - 1 > 0
+    /// Row and column, counted from the prompt's first row, at which byte
+    /// offset `offset` of the input is drawn on a terminal `columns` wide.
+    fn position_of(&self, offset: usize, columns: usize) -> (usize, usize) {
+        let mut position = buffer::advance((0, 0), self.prompt(), columns);
+        for (index, line) in self.buffer.text()[..offset].split('\n').enumerate() {
+            if index > 0 {
+                position = buffer::advance((position.0 + 1, 0), &self.continuation_prefix, columns);
+            }
+            position = buffer::advance(position, line, columns);
+        }
+        position
     }
 
-    fn cursor_can_go_right(&self) -> bool {
-        let (x, _) = self.get_relative_cursor_position();
-        x < self.current_input.len() as u16 + 1
+    /// Where the terminal shows the cursor after writing up to `position`: a
+    /// filled row puts it at the start of the next one.
+    fn on_screen(position: (usize, usize), columns: usize) -> (usize, usize) {
+        match position {
+            (row, column) if column >= columns => (row + 1, 0),
+            position => position,
+        }
     }
 
-    fn rewrite_from_position(&self) {
-        let (x, y) = self.get_relative_cursor_position();
-        let rest = self
-            .current_input
-            .chars()
-            .skip(x as usize - 1)
-            .collect::<String>();
+    /// Spans of the input for the highlighter, or none when there is no
+    /// highlighter or the theme has no colors.
+    fn input_spans(&self) -> Vec<highlight::Span> {
+        if self.highlighter.is_none() || !self.theme.colors {
+            return Vec::new();
+        }
+        let special: Vec<char> = self.special_commands.keys().cloned().collect();
+        highlight::spans(self.buffer.text(), &self.commands, &special)
+    }
 
-        self.write(format!(
-            "{ipos}{rest}{epos}",
-            ipos = cursor::Goto(x + self.prefix.len() as u16, y),
-            epos = cursor::Goto(x + 1 + self.prefix.len() as u16, y),
-            rest = rest,
-        ));
+    /// A line of the input, starting at byte `start`, in the input style and
+    /// the styles the highlighter gives its spans.
+    fn paint_line(&self, line: &str, spans: &[highlight::Span], start: usize) -> String {
+        match &self.highlighter {
+            Some(highlighter) if self.theme.colors => {
+                let spans = highlight::line_spans(spans, start, start + line.len());
+                highlight::paint(line, highlighter.highlight(line, &spans), &self.theme.input)
+            }
+            _ => self.theme.input.paint(line),
+        }
     }
 
-    fn rewrite_line(&self) {
-        self.clear_line();
-        self.write(self.current_input.clone());
+    /// Redraws the prompt from its first row: the prefix, each line of the
+    /// input and the suggestion popup when it is visible, leaving the cursor
+    /// at its place in the input. Long lines wrap across rows, so positions
+    /// are worked out from the display width of the text and the width of
+    /// the terminal rather than from lengths in bytes.
+    fn rewrite_line(&mut self) {
+        let columns = Terminal::columns();
+        let right = self.right_prompt(columns);
+        self.right_prompt_shown = !right.is_empty();
+        let mut output = format!(
+            "{up}\r{clear}{right}{prefix}",
+            up = Terminal::move_up(self.cursor_row),
+            clear = clear::AfterCursor,
+            right = right,
+            prefix = self.prompt(),
+        );
+        let spans = self.input_spans();
+        let mut line_start = 0;
+        for (index, line) in self.buffer.text().split('\n').enumerate() {
+            if index > 0 {
+                output.push_str("\r\n");
+                output.push_str(&self.theme.hint.paint(&self.continuation_prefix));
+            }
+            output.push_str(&self.paint_line(line, &spans, line_start));
+            line_start += line.len() + 1;
+        }
+
+        // Writing past a filled row moves the terminal onto the next one, so
+        // the cursor can be placed there.
+        let (mut row, column) = self.position_of(self.buffer.text().len(), columns);
+        if column >= columns {
+            output.push_str(" \r");
+            row += 1;
+        }
+
+        if self.suggestions_visible {
+            let (_, start, _) = self.completion_context();
+            let (rows, width) = self.render_suggestions(columns);
+            let popup_column = Terminal::on_screen(self.position_of(start, columns), columns)
+                .1
+                .min(columns.saturating_sub(width));
+            for popup_row in rows {
+                output.push_str("\r\n");
+                output.push_str(&Terminal::move_right(popup_column));
+                output.push_str(&popup_row);
+                row += (popup_column + width).div_ceil(columns).max(1);
+            }
+        }
+
+        let (cursor_row, cursor_column) =
+            Terminal::on_screen(self.position_of(self.buffer.cursor(), columns), columns);
+        output.push_str(&Terminal::move_up(row - cursor_row));
+        output.push('\r');
+        output.push_str(&Terminal::move_right(cursor_column));
+        self.cursor_row = cursor_row;
+        self.write(output);
+    }
+
+    /// Whether Enter should start a continuation line rather than submit: the
+    /// input ends inside quotes or after a backslash, or its command asks for
+    /// more lines.
+    fn is_incomplete(&self) -> bool {
+        let input = self.buffer.text();
+        match arguments::tokenize(input) {
+            Ok(tokens) => {
+                let words: Vec<String> = tokens.into_iter().map(|t| t.text).collect();
+                match self.commands.find(&words) {
+                    (node, depth) if depth > 0 => node
+                        .command()
+                        .is_some_and(|command| command.is_incomplete(input)),
+                    _ => false,
+                }
+            }
+            Err(arguments::ParseError::Empty) => false,
+            Err(_) => true,
+        }
     }
 
-    fn backspace(&self, count: u16) {
-        let (x, _) = self.get_relative_cursor_position();
-        let x = (x - count - 1) as usize;
-        let replace = self.current_input.chars().skip(x).collect::<String>();
+    /// Splits the text before the cursor into the words already typed, and
+    /// the start and unquoted text of the word being typed.
+    fn completion_context(&self) -> (Vec<String>, usize, String) {
+        let input = self.buffer.before_cursor();
+        let mut tokens = arguments::tokenize(input).unwrap_or_default();
 
-        if self.cursor_can_go_left() {
-            self.write(format!(
-                "{left}{rest}{space}{return_left}",
-                left = cursor::Left(count),
-                return_left = cursor::Left(count + replace.len() as u16),
-                rest = replace,
-                space = " ".to_string().repeat(count as usize),
-            ));
+        match tokens.last() {
+            Some(last) if last.end == input.len() => {
+                let last = tokens.pop().unwrap();
+                let words = tokens.into_iter().map(|t| t.text).collect();
+                (words, last.start, last.text)
+            }
+            _ => (
+                tokens.into_iter().map(|t| t.text).collect(),
+                input.len(),
+                "".to_string(),
+            ),
         }
     }
 
-    fn delete(&self, count: u16) {
-        let (x, y) = self.get_relative_cursor_position();
-        let x = (x - count) as usize;
-        if x == 0 {
-            self.rewrite_line();
-            self.write(cursor::Goto(self.prefix.len() as u16 + 1, y).to_string());
-            return;
+    fn current_suggestions(&self) -> Vec<completion::Suggestion> {
+        let (words, _, partial) = self.completion_context();
+
+        let (node, depth) = self.commands.find(&words);
+        let mut suggestions: Vec<completion::Suggestion> = Vec::new();
+
+        if depth == words.len() {
+            suggestions.extend(
+                node.children()
+                    .map(|(k, v)| completion::Suggestion::new(k, v.description())),
+            );
+        }
+        if words.is_empty() {
+            suggestions.extend(
+                self.special_suggestions
+                    .iter()
+                    .map(|(k, v)| completion::Suggestion::new(k, v)),
+            );
+            suggestions.sort_by(|a, b| a.value.cmp(&b.value));
         }
-        let replace = self.current_input.chars().skip(x).collect::<String>();
 
-        if self.cursor_can_go_left() {
-            self.write(format!(
-                "{rest}{space}{return_right}",
-                rest = replace,
-                space = " ".to_string().repeat(count as usize + 1),
-                return_right = cursor::Left(count + replace.len() as u16 + 1),
+        if let (Some(command), true) = (node.command(), depth > 0) {
+            suggestions.extend(completion::complete_arguments(
+                &command.flags(),
+                &command.positionals(),
+                &words[depth..],
+                &partial,
             ));
         }
+
+        completion::filter_suggestions(self.match_mode, &partial, suggestions)
     }
 
-    fn clear_line(&self) {
-        let mut lock = self.stdout.lock();
-        let (_, y) = lock.cursor_pos().unwrap();
-        self.write(format!("{}{}", clear::CurrentLine, cursor::Goto(1, y)));
-        self.write_prefix();
+    /// Replaces the word being typed with the selected suggestion.
+    fn accept_suggestion(&mut self) {
+        let suggestions = self.current_suggestions();
+        // A selection made before the cursor moved selects nothing.
+        let selected = self.suggestion_selection.checked_sub(1);
+        let suggestion = match selected.and_then(|index| suggestions.get(index)) {
+            Some(suggestion) => suggestion.clone(),
+            None => return,
+        };
+        let (_, start, _) = self.completion_context();
+        self.buffer
+            .replace_before_cursor(start, &arguments::escape(&suggestion.value));
     }
 
-    fn clear_before_cursor(&self) {
-        let mut lock = self.stdout.lock();
-        let (_, y) = lock.cursor_pos().unwrap();
-        self.write(format!(
-            "{}{}{}{}{}",
-            clear::CurrentLine,
-            cursor::Goto(1, y),
-            self.prefix,
-            self.current_input.clone(),
-            cursor::Goto(self.prefix.len() as u16 + 1, y)
-        ));
+    pub fn show_suggestions(&mut self) {
+        self.suggestions_visible = true;
+        self.rewrite_line();
     }
 
-    fn current_suggestions(&self) -> Vec<(String, String)> {
-        if self.current_input.len() > 0 {
-            self.suggestions
-                .clone()
-                .into_iter()
-                .filter(|(k, _)| k.starts_with(self.current_input.as_str()))
-                .collect()
-        } else {
-            self.suggestions.clone()
-        }
+    fn hide_suggestions(&mut self) {
+        self.suggestions_visible = false;
+        self.suggestion_selection = 0;
     }
 
-    pub fn show_suggestions(&self) {
+    /// Renders the rows of the suggestion popup, scrolled so that the
+    /// selection is visible when there are more suggestions than fit below
+    /// the prompt, along with the display width of every row. Descriptions
+    /// are cut short to keep rows within `columns` where possible.
+    fn render_suggestions(&self, columns: usize) -> (Vec<String>, usize) {
         let current_suggestions = self.current_suggestions();
 
-        if current_suggestions.len() == 0 {
-            self.clear_after_line();
-            self.rewrite_line();
-            return;
-        }
+        let (_, max_y) = termion::terminal_size().unwrap_or((80, 24));
+        let max_rows = (max_y as usize).saturating_sub(2).max(1);
+        let skip = self.suggestion_selection.saturating_sub(max_rows);
 
-        let (x, mut y) = self.get_cursor_position();
-        let (_, max_y) = termion::terminal_size().unwrap();
+        let mut longest_key: usize = 0;
+        let mut longest_value: usize = 0;
+        for suggestion in current_suggestions.iter() {
+            longest_key = longest_key.max(buffer::width(&suggestion.value));
+            longest_value = longest_value.max(buffer::width(&suggestion.description));
+        }
+        longest_value = longest_value.min(columns.saturating_sub(longest_key + 7));
 
-        if y + current_suggestions.len() as u16 + 1 > max_y {
-            let new_y = max_y - current_suggestions.len() as u16 - 1;
-            self.write("\r\n".repeat((1 + y - new_y).into()));
-            y = new_y;
+        let mut rows = Vec::new();
+        for (index, suggestion) in (1..).zip(current_suggestions).skip(skip).take(max_rows) {
+            let k = Terminal::highlight_matches(&suggestion, longest_key + 2);
+            let v = buffer::truncate(&suggestion.description, longest_value);
+            let v = format!("{}{}", v, " ".repeat(longest_value + 2 - buffer::width(v)));
+            let (key_style, value_style) = if index != self.suggestion_selection {
+                (&self.theme.popup_key, &self.theme.popup_description)
+            } else {
+                (
+                    &self.theme.popup_selected_key,
+                    &self.theme.popup_selected_description,
+                )
+            };
+            rows.push(format!(
+                "{} {}{}{}  {}{}",
+                key_style,
+                k,
+                style::Reset,
+                value_style,
+                v,
+                style::Reset,
+            ));
         }
+        (rows, longest_key + longest_value + 7)
+    }
 
-        let mut longest_key: usize = 0;
-        let mut longest_value: usize = 0;
-        for (k, v) in current_suggestions.clone().into_iter() {
-            if k.len() > longest_key {
-                longest_key = k.len()
-            }
-            if v.len() > longest_value {
-                longest_value = v.len()
+    /// Underlines the matched characters of a suggestion, padding it to `width`.
+    fn highlight_matches(suggestion: &completion::Suggestion, width: usize) -> String {
+        let mut key = String::new();
+        for (i, c) in suggestion.value.chars().enumerate() {
+            if suggestion.matched.contains(&i) {
+                key.push_str(&format!("{}{}{}", style::Underline, c, style::NoUnderline));
+            } else {
+                key.push(c);
             }
         }
+        key + &" ".repeat(width.saturating_sub(buffer::width(&suggestion.value)))
+    }
 
-        let key_fg = color::White;
-        let key_bg = color::LightBlue;
+    /// Finds the closest history entry containing `query`, starting at
+    /// `from` and moving towards older entries if `reverse` is set.
+    fn find_in_history(&self, query: &str, from: usize, reverse: bool) -> Option<usize> {
+        let entries = self.history.entries();
+        if reverse {
+            let from = from.min(entries.len().checked_sub(1)?);
+            (0..=from).rev().find(|&i| entries[i].contains(query))
+        } else {
+            (from..entries.len()).find(|&i| entries[i].contains(query))
+        }
+    }
 
-        let value_fg = color::Black;
-        let value_bg = color::Cyan;
+    fn write_search(&mut self, query: &str, found: Option<usize>, failed: bool, reverse: bool) {
+        let line = found.map_or(String::new(), |i| {
+            self.history.entries()[i].replace('\n', "\r\n")
+        });
+        let label = format!(
+            "({failed}{direction}i-search)",
+            failed = if failed { "failed " } else { "" },
+            direction = if reverse { "reverse-" } else { "" },
+        );
+        let search = format!(
+            "{label}'{query}': {line}",
+            label = self.theme.hint.paint(label),
+            query = query,
+            line = self.theme.input.paint(line),
+        );
+        let up = Terminal::move_up(self.cursor_row);
+        self.cursor_row = buffer::advance((0, 0), &search, Terminal::columns()).0;
+        self.right_prompt_shown = false;
+        self.write(format!(
+            "{up}\r{clear}{search}",
+            up = up,
+            clear = clear::AfterCursor,
+            search = search,
+        ));
+    }
 
-        let original_fg = color::Fg(color::Reset);
-        let original_bg = color::Bg(color::Reset);
+    /// Runs an incremental history search on the keys read from `keys`,
+    /// leaving the line found in the buffer. Ctrl-R and Ctrl-S move to
+    /// older and newer matches, Enter submits the match, Ctrl-G and Escape
+    /// cancel, and any other key goes back to editing the match.
+    fn search_history<I>(&mut self, keys: &mut I, mut reverse: bool) -> SearchEnd
+    where
+        I: Iterator<Item = io::Result<Key>>,
+    {
+        let original = self.buffer.clone();
+        let mut query = String::new();
+        let mut found: Option<usize> = None;
+        let mut failed = false;
 
-        self.write("\r\n");
+        self.write_search(&query, found, failed, reverse);
+        for c in keys.by_ref() {
+            let c = match c {
+                Ok(c) => c,
+                Err(_) => continue,
+            };
 
-        let mut index = 1;
-        for (k, v) in current_suggestions.into_iter() {
-            self.write(format!(
-                "{goto}{clear}",
-                goto = cursor::Goto(x, y + index),
-                clear = clear::CurrentLine,
-            ));
-            if index != self.suggestion_selection as u16 {
-                self.write(format!(
-                                "{key_fg}{key_bg} {key: <key_pad$}{value_fg}{value_bg}  {value: <value_pad$}{original_fg}{original_bg}\r\n",
-                                key_fg = color::Fg(key_fg),
-                                key_bg = color::Bg(key_bg),
-                                key = k,
-                                key_pad = longest_key + 2,
-                                value_fg = color::Fg(value_fg),
-                                value_bg = color::Bg(value_bg),
-                                value = v,
-                                value_pad = longest_value + 2,
-                                original_fg = original_fg,
-                                original_bg = original_bg,
-                            ));
-            } else {
-                // self.rewrite_line();
-                self.write(format!(
-                                "{key_fg}{key_bg} {key: <key_pad$}{value_fg}{value_bg}  {value: <value_pad$}{original_fg}{original_bg}\r\n",
-                                key_fg = color::Fg(value_fg),
-                                key_bg = color::Bg(value_bg),
-                                key = k,
-                                key_pad = longest_key + 2,
-                                value_fg = color::Fg(key_fg),
-                                value_bg = color::Bg(key_bg),
-                                value = v,
-                                value_pad = longest_value + 2,
-                                original_fg = original_fg,
-                                original_bg = original_bg,
-                            ));
+            let from = match c {
+                Key::Ctrl('r') => {
+                    reverse = true;
+                    found.map_or(Some(self.history.len()), |i| i.checked_sub(1))
+                }
+                Key::Ctrl('s') => {
+                    reverse = false;
+                    Some(found.map_or(0, |i| i + 1))
+                }
+                Key::Char('\n') => {
+                    if let Some(i) = found {
+                        self.buffer.set(&self.history.entries()[i]);
+                    }
+                    return SearchEnd::Accept;
+                }
+                Key::Ctrl('g') | Key::Esc => {
+                    self.buffer = original;
+                    return SearchEnd::Cancel;
+                }
+                Key::Backspace => {
+                    query.pop();
+                    Some(if reverse { self.history.len() } else { 0 })
+                }
+                Key::Char(c) => {
+                    query.push(c);
+                    Some(found.unwrap_or(if reverse { self.history.len() } else { 0 }))
+                }
+                _ => {
+                    if let Some(i) = found {
+                        self.buffer.set(&self.history.entries()[i]);
+                    }
+                    return SearchEnd::Edit(c);
+                }
+            };
+
+            let next = from.and_then(|from| self.find_in_history(&query, from, reverse));
+            failed = next.is_none();
+            if next.is_some() {
+                found = next;
             }
-            index += 1;
+            self.write_search(&query, found, failed, reverse);
         }
-        self.write(cursor::Goto(x, y).to_string());
-        if self.current_input.len() == 1 {
-            self.rewrite_line();
+
+        SearchEnd::Cancel
+    }
+
+    /// Saves killed text to the kill ring, joining it to the previous kill
+    /// when the keys before also killed text.
+    fn save_kill(&mut self, killed: String, chained: bool, backward: bool) {
+        if chained {
+            self.kill_ring.append(killed, backward);
+        } else {
+            self.kill_ring.push(killed);
         }
     }
 
-    fn clear_after_line(&self) {
-        let (_, max_y) = termion::terminal_size().unwrap();
-        let mut lock = self.stdout.lock();
-        let (x, y) = lock.cursor_pos().unwrap();
+    /// Redraws the line after its text changed, refreshing suggestions.
+    fn edited(&mut self) {
+        self.suggestion_selection = 0;
+        self.show_suggestions();
+    }
 
-        let mut index = 1;
-        while max_y > y + index {
-            self.write(format!(
-                "{}{}",
-                clear::CurrentLine,
-                cursor::Goto(1, y + index)
-            ));
-            index += 1;
-        }
-        self.write(format!("{}", cursor::Goto(x, y)));
-    }
-
-    pub fn read_chars(&mut self) -> String {
-        let lock = self.stdin.lock();
-        let mut history_index: usize = self.previous_input.len();
-
-        self.current_input = "".to_string();
-        for c in lock.keys() {
-            match c {
-                Ok(c) => match c {
-                    Key::Char('\n') => {
-                        self.write(clear::AfterCursor);
-                        if self.suggestion_selection > 0 {
-                            let (replace_input, _) =
-                                self.current_suggestions()[self.suggestion_selection - 1].clone();
-                            self.current_input = replace_input;
-                            break;
-                        }
-                        if self.current_input.is_empty() {
-                            self.write("\r\n");
-                            self.write_prefix();
-                            continue;
-                        }
-                        break;
-                    }
-                    Key::BackTab => {
-                        if (self.suggestion_selection as usize) > 0 {
-                            self.suggestion_selection -= 1;
-                            self.show_suggestions();
-                        }
-                    }
-                    Key::Char('\t') => {
-                        if (self.suggestion_selection as usize) < self.current_suggestions().len() {
-                            self.suggestion_selection += 1;
-                            self.show_suggestions();
-                        }
-                    }
-                    Key::Char(' ') => {
-                        if self.suggestion_selection == 0 {
-                            self.suggestion_selection = 0;
-                            let (x, _) = self.get_relative_cursor_position();
-                            self.current_input.insert((x - 1) as usize, ' ');
-                            self.rewrite_from_position();
-                            self.show_suggestions();
-                            continue;
-                        }
+    /// Inserts a typed character. Space accepts the selected suggestion.
+    fn insert_char(&mut self, c: char) {
+        if c == ' ' && self.suggestion_selection > 0 {
+            self.accept_suggestion();
+            // Directories are left open so their contents can be completed.
+            if self.buffer.before_cursor().ends_with('/') {
+                self.edited();
+                return;
+            }
+        }
+        self.buffer.insert(c);
+        self.edited();
+    }
 
-                        let (new_input, _) =
-                            self.current_suggestions()[self.suggestion_selection - 1].clone();
-                        self.suggestion_selection = 0;
-                        self.clear_after_line();
-                        self.current_input = new_input + " ";
-                        self.rewrite_line();
-                    }
-                    Key::Char(c) => {
-                        self.suggestion_selection = 0;
-                        let (x, _) = self.get_relative_cursor_position();
-                        self.current_input.insert((x - 1) as usize, c);
-                        self.rewrite_from_position();
-                        self.show_suggestions();
-                    }
-                    Key::Backspace => {
-                        let (x, _) = self.get_relative_cursor_position();
-                        let x = x as usize;
-                        if x == 1 {
-                            continue;
-                        }
-                        self.current_input.remove(x - 2);
-                        self.backspace(1);
+    /// Runs a custom key binding.
+    fn run_callback(&mut self, callback: &keymap::Callback) -> Flow {
+        match callback(&mut self.buffer) {
+            keymap::CallbackResult::Continue => {
+                self.edited();
+                Flow::Continue
+            }
+            keymap::CallbackResult::Accept => {
+                self.hide_suggestions();
+                self.buffer.move_end();
+                self.rewrite_line();
+                Flow::Accept
+            }
+            keymap::CallbackResult::Execute(line) => {
+                if line.trim().is_empty() {
+                    return Flow::Continue;
+                }
+                // The command's output goes below the input, which is then
+                // drawn again with the cursor where it was.
+                self.hide_suggestions();
+                let cursor = self.buffer.cursor();
+                self.buffer.move_end();
+                self.rewrite_line();
+                self.buffer.set_cursor(cursor);
+                if !self.run_line(&line) {
+                    return Flow::Exit;
+                }
+                self.cursor_row = 0;
+                self.rewrite_line();
+                Flow::Continue
+            }
+        }
+    }
+
+    /// Runs a built-in editor action.
+    fn perform<I>(
+        &mut self,
+        action: keymap::Action,
+        state: &mut ReadState,
+        previous: LastEdit,
+        keys: &mut I,
+    ) -> Flow
+    where
+        I: Iterator<Item = io::Result<Key>>,
+    {
+        use keymap::Action;
+
+        let motion = matches!(
+            action,
+            Action::BackwardChar
+                | Action::ForwardChar
+                | Action::BackwardWord
+                | Action::ForwardWord
+                | Action::LineStart
+                | Action::LineEnd
+                | Action::PreviousHistory
+                | Action::NextHistory
+        );
+        if motion {
+            // The selection belongs to the word the cursor was on.
+            self.suggestion_selection = 0;
+        } else {
+            // Editing the line starts a new prefix for history navigation.
+            state.history_index = self.history.len();
+        }
+
+        match action {
+            Action::AcceptLine => {
+                if self.suggestion_selection > 0 {
+                    self.accept_suggestion();
+                } else if self.buffer.is_empty() {
+                    self.hide_suggestions();
+                    self.rewrite_line();
+                    self.new_line();
+                    self.write_prefix();
+                    return Flow::Continue;
+                }
+                self.hide_suggestions();
+                if self.is_incomplete() {
+                    self.buffer.insert('\n');
+                    self.rewrite_line();
+                    return Flow::Continue;
+                }
+                self.buffer.move_end();
+                self.rewrite_line();
+                return Flow::Accept;
+            }
+            Action::NextSuggestion => {
+                if self.suggestion_selection < self.current_suggestions().len() {
+                    self.suggestion_selection += 1;
+                }
+                self.show_suggestions();
+            }
+            Action::PreviousSuggestion if self.suggestion_selection > 0 => {
+                self.suggestion_selection -= 1;
+                self.show_suggestions();
+            }
+            Action::PreviousSuggestion => {}
+            Action::HideSuggestions => {
+                self.hide_suggestions();
+                self.rewrite_line();
+            }
+            Action::BackwardDeleteChar => {
+                self.suggestion_selection = 0;
+                if self.buffer.backspace() {
+                    self.show_suggestions();
+                }
+            }
+            Action::DeleteCharOrEof if self.buffer.is_empty() => return Flow::Exit,
+            Action::DeleteChar | Action::DeleteCharOrEof => {
+                self.suggestion_selection = 0;
+                if self.buffer.delete() {
+                    self.show_suggestions();
+                }
+            }
+            Action::BackwardChar => {
+                if self.buffer.move_left() {
+                    self.rewrite_line();
+                }
+            }
+            Action::ForwardChar => {
+                if self.buffer.move_right() {
+                    self.rewrite_line();
+                }
+            }
+            Action::BackwardWord => {
+                if self.buffer.move_word_left() {
+                    self.rewrite_line();
+                }
+            }
+            Action::ForwardWord => {
+                if self.buffer.move_word_right() {
+                    self.rewrite_line();
+                }
+            }
+            Action::LineStart => {
+                self.buffer.move_line_start();
+                self.rewrite_line();
+            }
+            Action::LineEnd => {
+                self.buffer.move_line_end();
+                self.rewrite_line();
+            }
+            Action::PreviousHistory if self.buffer.move_up() => self.rewrite_line(),
+            Action::NextHistory if self.buffer.move_down() => self.rewrite_line(),
+            Action::PreviousHistory => {
+                if state.history_index == self.history.len() {
+                    state.history_prefix = self.buffer.text().to_string();
+                }
+                let older = self.history.search_prefix(
+                    &state.history_prefix,
+                    state.history_index,
+                    true,
+                    self.buffer.text(),
+                );
+                if let Some(index) = older {
+                    state.history_index = index;
+                    self.buffer.set(&self.history.entries()[index]);
+                    self.hide_suggestions();
+                    self.rewrite_line();
+                }
+            }
+            Action::NextHistory => {
+                if state.history_index == self.history.len() {
+                    return Flow::Continue;
+                }
+                let newer = self.history.search_prefix(
+                    &state.history_prefix,
+                    state.history_index,
+                    false,
+                    self.buffer.text(),
+                );
+                match newer {
+                    Some(index) => {
+                        state.history_index = index;
+                        self.buffer.set(&self.history.entries()[index]);
                     }
-                    Key::Delete => {
-                        let (x, _) = self.get_relative_cursor_position();
-                        let x = x as usize;
-                        if x > self.current_input.len() {
-                            continue;
-                        }
-                        self.current_input.remove(x - 1);
-                        self.delete(1);
+                    None => {
+                        state.history_index = self.history.len();
+                        self.buffer.set(&state.history_prefix);
                     }
-                    Key::Left => {
-                        if self.cursor_can_go_left() {
-                            self.write(format!("{}", cursor::Left(1)));
-                        }
+                }
+                self.hide_suggestions();
+                self.rewrite_line();
+            }
+            Action::ReverseSearchHistory | Action::ForwardSearchHistory => {
+                self.hide_suggestions();
+                let reverse = action == Action::ReverseSearchHistory;
+                let end = self.search_history(keys, reverse);
+                self.rewrite_line();
+                match end {
+                    SearchEnd::Accept => return Flow::Accept,
+                    // Like readline, the key ending the search edits the
+                    // found line.
+                    SearchEnd::Edit(key) => return self.dispatch(key, state, keys),
+                    SearchEnd::Cancel => {}
+                }
+            }
+            Action::KillToStart => {
+                let killed = self.buffer.kill_to_start();
+                self.save_kill(killed, previous == LastEdit::Kill, true);
+                state.last_edit = LastEdit::Kill;
+                self.hide_suggestions();
+                self.rewrite_line();
+            }
+            Action::KillToEnd => {
+                let killed = self.buffer.kill_to_end();
+                self.save_kill(killed, previous == LastEdit::Kill, false);
+                state.last_edit = LastEdit::Kill;
+                self.edited();
+            }
+            Action::KillPreviousWord => {
+                let killed = self.buffer.kill_previous_word();
+                self.save_kill(killed, previous == LastEdit::Kill, true);
+                state.last_edit = LastEdit::Kill;
+                self.edited();
+            }
+            Action::KillNextWord => {
+                let killed = self.buffer.kill_next_word();
+                self.save_kill(killed, previous == LastEdit::Kill, false);
+                state.last_edit = LastEdit::Kill;
+                self.edited();
+            }
+            Action::Yank => {
+                if let Some(text) = self.kill_ring.yank() {
+                    state.last_edit = LastEdit::Yank(self.buffer.cursor());
+                    self.buffer.insert_str(text);
+                    self.edited();
+                }
+            }
+            Action::YankPop => {
+                if let LastEdit::Yank(start) = previous {
+                    if let Some(text) = self.kill_ring.yank_pop() {
+                        self.buffer.replace_before_cursor(start, text);
+                        state.last_edit = LastEdit::Yank(start);
+                        self.edited();
                     }
-                    Key::Right => {
-                        if self.cursor_can_go_right() {
-                            self.write(format!("{}", cursor::Right(1)));
+                }
+            }
+            Action::TransposeChars => {
+                if self.buffer.transpose() {
+                    self.edited();
+                }
+            }
+            Action::UpcaseWord => {
+                if self.buffer.change_case(buffer::Case::Upper) {
+                    self.edited();
+                }
+            }
+            Action::DowncaseWord => {
+                if self.buffer.change_case(buffer::Case::Lower) {
+                    self.edited();
+                }
+            }
+            Action::CapitalizeWord => {
+                if self.buffer.change_case(buffer::Case::Capitalize) {
+                    self.edited();
+                }
+            }
+            Action::Undo | Action::Redo => {
+                if action == Action::Undo {
+                    self.undo();
+                } else {
+                    self.redo();
+                }
+                state.last_edit = LastEdit::Undo;
+                self.edited();
+            }
+            Action::EditInEditor => {
+                self.hide_suggestions();
+                let cursor = self.buffer.cursor();
+                self.buffer.move_end();
+                self.rewrite_line();
+                self.buffer.set_cursor(cursor);
+                self.new_line();
+                if let Err(e) = self.edit_in_editor() {
+                    self.write(self.failure(format!("could not edit the line: {}", e)));
+                    self.new_line();
+                }
+                self.cursor_row = 0;
+                self.rewrite_line();
+            }
+            Action::ClearScreen => {
+                self.write(format!("{}{}", clear::All, cursor::Goto(1, 1)));
+                self.cursor_row = 0;
+                self.rewrite_line();
+            }
+        }
+        Flow::Continue
+    }
+
+    /// Handles one key read while editing a line.
+    fn dispatch<I>(&mut self, c: Key, state: &mut ReadState, keys: &mut I) -> Flow
+    where
+        I: Iterator<Item = io::Result<Key>>,
+    {
+        let previous = std::mem::take(&mut state.last_edit);
+
+        let c = match self.edit_mode {
+            EditMode::Emacs => c,
+            EditMode::Vi => {
+                let outcome = self.vi.handle(c, &mut self.buffer, &mut self.kill_ring);
+                if let vi::Outcome::Pass(key) = outcome {
+                    key
+                } else {
+                    match outcome {
+                        vi::Outcome::Edited => state.history_index = self.history.len(),
+                        vi::Outcome::Moved => self.suggestion_selection = 0,
+                        vi::Outcome::Undo => {
+                            self.undo();
+                            state.last_edit = LastEdit::Undo;
                         }
+                        _ => {}
                     }
-                    Key::Up => {
-                        if history_index == 0 {
-                            continue;
-                        }
-                        self.clear_line();
-                        history_index -= 1;
-                        self.write(self.previous_input[history_index].clone());
-                        self.current_input = self.previous_input[history_index].clone().to_string();
+                    // Suggestions are only offered while inserting text.
+                    if self.vi.mode() == vi::Mode::Normal {
+                        self.hide_suggestions();
                     }
-                    Key::Down => {
-                        if self.previous_input.is_empty()
-                            || history_index == self.previous_input.len() - 1
-                        {
-                            continue;
-                        }
-                        self.clear_line();
-                        history_index += 1;
-                        self.write(self.previous_input[history_index].clone());
-                        self.current_input = self.previous_input[history_index].clone().to_string();
+                    if outcome != vi::Outcome::Pending {
+                        self.rewrite_line();
                     }
-                    Key::Ctrl('u') => {
-                        let (x, _) = self.get_relative_cursor_position();
+                    return Flow::Continue;
+                }
+            }
+        };
 
-                        self.current_input = self
-                            .current_input
-                            .chars()
-                            .skip(x as usize - 1)
-                            .collect::<String>();
+        state.chord.push(c);
+        let binding = match self.keymap.lookup(&state.chord) {
+            keymap::Lookup::Prefix => return Flow::Continue,
+            keymap::Lookup::Found(binding) => binding.clone(),
+            keymap::Lookup::None => {
+                // Characters without a binding of their own are typed.
+                if let [Key::Char(c)] = state.chord[..] {
+                    state.history_index = self.history.len();
+                    state.last_edit = LastEdit::Insert;
+                    self.insert_char(c);
+                }
+                state.chord.clear();
+                return Flow::Continue;
+            }
+        };
+        state.chord.clear();
 
-                        self.clear_before_cursor();
-                    }
-                    Key::End => {
-                        let mut lock = self.stdout.lock();
-                        let (_, y) = lock.cursor_pos().unwrap();
-                        let x = self.prefix.len() + self.current_input.len() + 1;
-                        self.write(cursor::Goto(x as u16, y).to_string());
-                    }
-                    Key::Home => {
-                        let mut lock = self.stdout.lock();
-                        let (_, y) = lock.cursor_pos().unwrap();
-                        let x = self.prefix.len() + 1;
-                        self.write(cursor::Goto(x as u16, y).to_string());
-                    }
-                    Key::Ctrl('l') => {
-                        self.write(format!("{}{}", clear::All, cursor::Goto(1, 1)));
-                        self.write_prefix();
+        match binding {
+            keymap::Binding::Action(action) => self.perform(action, state, previous, keys),
+            keymap::Binding::Callback(callback) => {
+                state.history_index = self.history.len();
+                self.run_callback(&callback)
+            }
+        }
+    }
+
+    /// Opens the line in `$VISUAL` or `$EDITOR`, falling back to `vi`, and
+    /// loads what was saved back into the buffer. Raw mode is suspended while
+    /// the editor runs.
+    fn edit_in_editor(&mut self) -> io::Result<()> {
+        let (path, file) = create_temp_file()?;
+        let edited = self.run_editor(&path, file);
+        // The file holds the line, so it is removed however editing went,
+        // but failing to remove it is not worth losing the edited line over.
+        let _ = fs::remove_file(&path);
+
+        let edited = edited?.replace("\r\n", "\n");
+        self.buffer.set(edited.trim_end_matches('\n'));
+        Ok(())
+    }
+
+    /// Writes the line to `file`, at `path`, and returns what it holds once
+    /// the editor exits.
+    fn run_editor(&mut self, path: &Path, mut file: fs::File) -> io::Result<String> {
+        let editor = env::var("VISUAL")
+            .or_else(|_| env::var("EDITOR"))
+            .unwrap_or_else(|_| "vi".to_string());
+        writeln!(file, "{}", self.buffer.text())?;
+        drop(file);
+
+        self.stdout.suspend_raw_mode()?;
+        // The editor may be given with arguments, like `code --wait`.
+        let status = process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$1\"", editor))
+            .arg("sh")
+            .arg(path)
+            .status();
+        self.stdout.activate_raw_mode()?;
+
+        match status {
+            Ok(status) if status.success() => fs::read_to_string(path),
+            Ok(status) => Err(io::Error::other(format!(
+                "{} exited with {}",
+                editor, status
+            ))),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Restores the line as it was before the last edit.
+    fn undo(&mut self) {
+        if let Some(previous) = self.undo.undo(&self.buffer) {
+            self.buffer = previous;
+        }
+    }
+
+    /// Reapplies the last edit undone.
+    fn redo(&mut self) {
+        if let Some(next) = self.undo.redo(&self.buffer) {
+            self.buffer = next;
+        }
+    }
+
+    /// Reads and edits a line until it is submitted, returning it trimmed.
+    /// Returns `None` once input ends, Ctrl-D is pressed on an empty line or
+    /// a command run by a key binding exits.
+    pub fn read_chars(&mut self) -> Option<String> {
+        let mut state = ReadState {
+            history_index: self.history.len(),
+            ..ReadState::default()
+        };
+
+        self.buffer.clear();
+        self.undo.clear();
+        self.cursor_row = 0;
+        self.hide_suggestions();
+        self.vi.reset();
+        let mut keys = UnbufferedStdin.keys();
+        let mut size = termion::terminal_size().ok();
+        loop {
+            // Waiting with a timeout lets the line be redrawn for a new
+            // terminal size while no key is pressed.
+            if !UnbufferedStdin::wait(RESIZE_POLL_MS) {
+                let new_size = termion::terminal_size().ok();
+                if new_size != size {
+                    let old_columns = size.map_or(80, |(x, _)| x as usize);
+                    size = new_size;
+                    // Terminals rewrap soft-wrapped rows to their new width,
+                    // which moves the cursor to where the new width puts it.
+                    let columns = Terminal::columns();
+                    self.cursor_row = Terminal::on_screen(
+                        self.position_of(self.buffer.cursor(), columns),
+                        columns,
+                    )
+                    .0;
+                    // The right prompt made the first row as long as the old
+                    // width, so the rows below move down by what it now
+                    // wraps onto.
+                    if self.right_prompt_shown && self.buffer.cursor_line() > 0 {
+                        let text = self.buffer.text();
+                        let first_line = text.find('\n').unwrap_or(text.len());
+                        let rows = self.position_of(first_line, columns).0 + 1;
+                        let shown_rows = old_columns.saturating_sub(1).div_ceil(columns);
+                        self.cursor_row += shown_rows.saturating_sub(rows);
                     }
-                    _ => {}
-                },
-                Err(err) => {
+                    self.rewrite_line();
+                }
+                continue;
+            }
+
+            let c = match keys.next() {
+                Some(Ok(c)) => c,
+                Some(Err(err)) => {
                     println!("{:?}", err);
+                    continue;
                 }
+                None => return None,
+            };
+
+            // Any key that changes the text is recorded for undo, with runs
+            // of typed characters recorded as a single edit.
+            let before = self.buffer.clone();
+            let flow = self.dispatch(c, &mut state, &mut keys);
+            let changed = self.buffer.text() != before.text();
+            match state.last_edit {
+                LastEdit::Undo => {}
+                _ if changed => self
+                    .undo
+                    .record(before, state.last_edit == LastEdit::Insert),
+                _ => self.undo.seal(),
+            }
+
+            match flow {
+                Flow::Continue => {}
+                Flow::Accept => break,
+                Flow::Exit => return None,
             }
         }
 
-        let previous_input_len = self.previous_input.len();
-        if previous_input_len == 0
-            || self.previous_input[previous_input_len - 1] != self.current_input
-        {
-            self.previous_input.push(self.current_input.clone());
+        if let Err(e) = self.history.add(self.buffer.text()) {
+            self.write(format!(
+                "\r\n{}",
+                self.failure(format!("could not save history: {}", e))
+            ));
+        }
+        Some(self.buffer.text().trim().to_string())
+    }
+}
+
+/// Creates a file only the user can read, under a name nobody could have
+/// prepared a file or link at in advance.
+fn create_temp_file() -> io::Result<(PathBuf, fs::File)> {
+    let dir = env::temp_dir();
+    let mut attempts = 0;
+    loop {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.subsec_nanos());
+        let name = format!("rust_prompt-{}-{:08x}.txt", process::id(), nanos ^ attempts);
+        let path = dir.join(name);
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path);
+        match file {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempts < 100 => {
+                attempts += 1;
+            }
+            Err(e) => return Err(e),
         }
-        self.current_input.clone().trim().to_string()
     }
 }
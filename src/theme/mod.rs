@@ -0,0 +1,304 @@
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+use termion::{color, style};
+
+/// A terminal color: one of the 16 named colors, a 256-color palette index
+/// or a 24-bit color. In configuration files colors are written as names
+/// like `light-blue`, palette indexes like `208` or hex like `#ff8700`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    LightBlack,
+    LightRed,
+    LightGreen,
+    LightYellow,
+    LightBlue,
+    LightMagenta,
+    LightCyan,
+    LightWhite,
+    Ansi(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    fn termion(self) -> Box<dyn color::Color> {
+        match self {
+            Color::Black => Box::new(color::Black),
+            Color::Red => Box::new(color::Red),
+            Color::Green => Box::new(color::Green),
+            Color::Yellow => Box::new(color::Yellow),
+            Color::Blue => Box::new(color::Blue),
+            Color::Magenta => Box::new(color::Magenta),
+            Color::Cyan => Box::new(color::Cyan),
+            Color::White => Box::new(color::White),
+            Color::LightBlack => Box::new(color::LightBlack),
+            Color::LightRed => Box::new(color::LightRed),
+            Color::LightGreen => Box::new(color::LightGreen),
+            Color::LightYellow => Box::new(color::LightYellow),
+            Color::LightBlue => Box::new(color::LightBlue),
+            Color::LightMagenta => Box::new(color::LightMagenta),
+            Color::LightCyan => Box::new(color::LightCyan),
+            Color::LightWhite => Box::new(color::LightWhite),
+            Color::Ansi(value) => Box::new(color::AnsiValue(value)),
+            Color::Rgb(r, g, b) => Box::new(color::Rgb(r, g, b)),
+        }
+    }
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(hex) = s.strip_prefix('#') {
+            let channel = |i: usize| {
+                hex.get(i..i + 2)
+                    .and_then(|c| u8::from_str_radix(c, 16).ok())
+            };
+            return match (hex.len(), channel(0), channel(2), channel(4)) {
+                (6, Some(r), Some(g), Some(b)) => Ok(Color::Rgb(r, g, b)),
+                _ => Err(format!("invalid hex color `{}`", s)),
+            };
+        }
+        if let Ok(value) = s.parse::<u8>() {
+            return Ok(Color::Ansi(value));
+        }
+        let name: String = s
+            .chars()
+            .filter(|c| *c != '-' && *c != '_')
+            .collect::<String>()
+            .to_lowercase();
+        let color = match name.as_str() {
+            "black" => Color::Black,
+            "red" => Color::Red,
+            "green" => Color::Green,
+            "yellow" => Color::Yellow,
+            "blue" => Color::Blue,
+            "magenta" => Color::Magenta,
+            "cyan" => Color::Cyan,
+            "white" => Color::White,
+            "lightblack" | "gray" | "grey" => Color::LightBlack,
+            "lightred" => Color::LightRed,
+            "lightgreen" => Color::LightGreen,
+            "lightyellow" => Color::LightYellow,
+            "lightblue" => Color::LightBlue,
+            "lightmagenta" => Color::LightMagenta,
+            "lightcyan" => Color::LightCyan,
+            "lightwhite" => Color::LightWhite,
+            _ => return Err(format!("unknown color `{}`", s)),
+        };
+        Ok(color)
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Colors and attributes of a part of the interface. It is displayed as the
+/// escape sequences that turn it on, which are undone by `style::Reset`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+}
+
+impl Style {
+    /// A style that leaves the text as it is.
+    pub fn new() -> Style {
+        Style::default()
+    }
+
+    pub fn fg(mut self, color: Color) -> Style {
+        self.fg = Some(color);
+        self
+    }
+
+    pub fn bg(mut self, color: Color) -> Style {
+        self.bg = Some(color);
+        self
+    }
+
+    pub fn bold(mut self) -> Style {
+        self.bold = true;
+        self
+    }
+
+    pub fn italic(mut self) -> Style {
+        self.italic = true;
+        self
+    }
+
+    pub fn underline(mut self) -> Style {
+        self.underline = true;
+        self
+    }
+
+    /// Swaps the foreground and background colors.
+    pub fn reverse(mut self) -> Style {
+        self.reverse = true;
+        self
+    }
+
+    /// The style with its attributes but without its colors.
+    pub fn without_colors(self) -> Style {
+        Style {
+            fg: None,
+            bg: None,
+            ..self
+        }
+    }
+
+    pub fn is_plain(&self) -> bool {
+        *self == Style::default()
+    }
+
+    /// `text` in this style, followed by a reset when the style changes
+    /// anything.
+    pub fn paint<S: AsRef<str>>(&self, text: S) -> String {
+        if self.is_plain() {
+            return text.as_ref().to_string();
+        }
+        format!("{}{}{}", self, text.as_ref(), style::Reset)
+    }
+}
+
+impl fmt::Display for Style {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(fg) = self.fg {
+            fg.termion().write_fg(f)?;
+        }
+        if let Some(bg) = self.bg {
+            bg.termion().write_bg(f)?;
+        }
+        if self.bold {
+            write!(f, "{}", style::Bold)?;
+        }
+        if self.italic {
+            write!(f, "{}", style::Italic)?;
+        }
+        if self.underline {
+            write!(f, "{}", style::Underline)?;
+        }
+        if self.reverse {
+            write!(f, "{}", style::Invert)?;
+        }
+        Ok(())
+    }
+}
+
+/// Styles of every part of the interface.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Theme {
+    /// Text of the prompt, below the styles of its segments.
+    pub prompt: Style,
+    pub input: Style,
+    /// Suggestion values in the popup.
+    pub popup_key: Style,
+    /// Suggestion descriptions in the popup.
+    pub popup_description: Style,
+    pub popup_selected_key: Style,
+    pub popup_selected_description: Style,
+    /// The `[SUCCESS]` label before a command's output.
+    pub success: Style,
+    /// The `[FAILURE]` label before an error.
+    pub failure: Style,
+    /// Secondary text: the continuation prefix, the vi mode and the history
+    /// search label.
+    pub hint: Style,
+    /// Whether styles given outside of the theme, like those of prompt
+    /// segments, are drawn.
+    pub colors: bool,
+}
+
+impl Default for Theme {
+    /// The dark theme, or no colors at all when `NO_COLOR` is set.
+    fn default() -> Self {
+        Theme::dark().or_no_color()
+    }
+}
+
+/// Whether the user asked for no colors by setting `NO_COLOR` to anything
+/// but an empty string.
+pub fn no_color_requested() -> bool {
+    env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+impl Theme {
+    /// The theme, or `Theme::no_color()` when `NO_COLOR` is set.
+    pub fn or_no_color(self) -> Theme {
+        if no_color_requested() {
+            Theme::no_color()
+        } else {
+            self
+        }
+    }
+
+    /// Colors for terminals with a dark background.
+    pub fn dark() -> Theme {
+        Theme {
+            prompt: Style::new(),
+            input: Style::new(),
+            popup_key: Style::new().fg(Color::White).bg(Color::LightBlue),
+            popup_description: Style::new().fg(Color::Black).bg(Color::Cyan),
+            popup_selected_key: Style::new().fg(Color::Black).bg(Color::Cyan),
+            popup_selected_description: Style::new().fg(Color::White).bg(Color::LightBlue),
+            success: Style::new().fg(Color::LightGreen),
+            failure: Style::new().fg(Color::LightRed),
+            hint: Style::new().fg(Color::LightBlack),
+            colors: true,
+        }
+    }
+
+    /// Colors for terminals with a light background.
+    pub fn light() -> Theme {
+        Theme {
+            prompt: Style::new(),
+            input: Style::new(),
+            popup_key: Style::new().fg(Color::White).bg(Color::Blue),
+            popup_description: Style::new().fg(Color::Black).bg(Color::LightCyan),
+            popup_selected_key: Style::new().fg(Color::Black).bg(Color::LightCyan),
+            popup_selected_description: Style::new().fg(Color::White).bg(Color::Blue),
+            success: Style::new().fg(Color::Green),
+            failure: Style::new().fg(Color::Red),
+            hint: Style::new().fg(Color::LightBlack),
+            colors: true,
+        }
+    }
+
+    /// No colors, for `NO_COLOR`. The selected suggestion is shown in
+    /// reverse video.
+    pub fn no_color() -> Theme {
+        Theme {
+            prompt: Style::new(),
+            input: Style::new(),
+            popup_key: Style::new(),
+            popup_description: Style::new(),
+            popup_selected_key: Style::new().reverse(),
+            popup_selected_description: Style::new().reverse(),
+            success: Style::new(),
+            failure: Style::new(),
+            hint: Style::new(),
+            colors: false,
+        }
+    }
+}
@@ -0,0 +1,691 @@
+use crate::buffer::{KillRing, LineBuffer};
+use termion::event::Key;
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Insert,
+    Normal,
+}
+
+impl Mode {
+    /// Text shown before the prompt while in this mode, as readline does with
+    /// `show-mode-in-prompt`.
+    pub fn indicator(self) -> &'static str {
+        match self {
+            Mode::Insert => "(ins) ",
+            Mode::Normal => "(cmd) ",
+        }
+    }
+}
+
+/// What became of a key given to `Vi::handle`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The key moved the cursor or changed mode.
+    Moved,
+    /// The key changed the text.
+    Edited,
+    /// The key is part of a command still being typed.
+    Pending,
+    /// The key asks for the last edit to be undone.
+    Undo,
+    /// The key is not a vi command and is handled as it is outside vi mode.
+    Pass(Key),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Motion {
+    Left,
+    Right,
+    /// Start of the next word, counting punctuation as words unless the
+    /// flag asks for whitespace separated words.
+    WordForward(bool),
+    WordBackward(bool),
+    WordEnd(bool),
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    Find(char),
+    Till(char),
+    FindBack(char),
+    TillBack(char),
+    /// The whole line, for doubled operators like `dd`.
+    Line,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Insert {
+    Cursor,
+    After,
+    LineStart,
+    LineEnd,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Action {
+    Move(Motion),
+    Operate(Operator, Motion),
+    Insert(Insert),
+    Replace(char),
+    Put { before: bool },
+    Undo,
+    Repeat,
+}
+
+enum Parsed<T> {
+    Incomplete,
+    Invalid,
+    Done(T),
+}
+
+fn grapheme_at(text: &str, offset: usize) -> &str {
+    text[offset..].graphemes(true).next().unwrap_or("")
+}
+
+fn next(text: &str, offset: usize) -> usize {
+    offset + grapheme_at(text, offset).len()
+}
+
+fn previous(text: &str, offset: usize) -> usize {
+    text[..offset]
+        .grapheme_indices(true)
+        .next_back()
+        .map_or(offset, |(i, _)| i)
+}
+
+fn line_start(text: &str, offset: usize) -> usize {
+    text[..offset].rfind('\n').map_or(0, |i| i + 1)
+}
+
+fn line_end(text: &str, offset: usize) -> usize {
+    text[offset..].find('\n').map_or(text.len(), |i| offset + i)
+}
+
+/// Sorts graphemes for word motions: 0 for whitespace and the end of the
+/// text, 1 for word characters and 2 for punctuation. With `big` set, every
+/// non-blank grapheme is a word character.
+fn class(grapheme: &str, big: bool) -> u8 {
+    if grapheme.chars().all(char::is_whitespace) {
+        0
+    } else if big || grapheme.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        1
+    } else {
+        2
+    }
+}
+
+impl Motion {
+    /// Whether an operator using this motion includes the grapheme it ends on.
+    fn inclusive(self) -> bool {
+        matches!(
+            self,
+            Motion::WordEnd(_) | Motion::LineEnd | Motion::Find(_) | Motion::Till(_)
+        )
+    }
+
+    /// Where the motion goes from `offset`, or `None` when it cannot move.
+    fn target(self, text: &str, offset: usize) -> Option<usize> {
+        let start = line_start(text, offset);
+        let end = line_end(text, offset);
+        let class_at = |offset: usize, big: bool| class(grapheme_at(text, offset), big);
+
+        let target = match self {
+            Motion::Left if offset > start => previous(text, offset),
+            Motion::Left => return None,
+            Motion::Right if offset < end => next(text, offset),
+            Motion::Right => return None,
+            Motion::WordForward(big) => {
+                let mut target = offset;
+                let class = class_at(target, big);
+                while class != 0 && target < text.len() && class_at(target, big) == class {
+                    target = next(text, target);
+                }
+                while target < text.len() && class_at(target, big) == 0 {
+                    target = next(text, target);
+                }
+                target
+            }
+            Motion::WordEnd(big) => {
+                let mut target = next(text, offset);
+                while target < text.len() && class_at(target, big) == 0 {
+                    target = next(text, target);
+                }
+                if target == text.len() {
+                    return None;
+                }
+                let class = class_at(target, big);
+                while next(text, target) < text.len() && class_at(next(text, target), big) == class
+                {
+                    target = next(text, target);
+                }
+                target
+            }
+            Motion::WordBackward(big) => {
+                if offset == 0 {
+                    return None;
+                }
+                let mut target = previous(text, offset);
+                while target > 0 && class_at(target, big) == 0 {
+                    target = previous(text, target);
+                }
+                let class = class_at(target, big);
+                while target > 0 && class != 0 && class_at(previous(text, target), big) == class {
+                    target = previous(text, target);
+                }
+                target
+            }
+            Motion::LineStart | Motion::Line => start,
+            Motion::FirstNonBlank => {
+                let mut target = start;
+                while target < end && class_at(target, false) == 0 {
+                    target = next(text, target);
+                }
+                target
+            }
+            Motion::LineEnd => previous(text, end).max(start),
+            Motion::Find(c) | Motion::Till(c) => {
+                let after = next(text, offset).min(end);
+                let found = text[after..end]
+                    .grapheme_indices(true)
+                    .find(|(_, g)| g.starts_with(c))
+                    .map(|(i, _)| after + i)?;
+                match self {
+                    Motion::Till(_) => previous(text, found),
+                    _ => found,
+                }
+            }
+            Motion::FindBack(c) | Motion::TillBack(c) => {
+                let found = text[start..offset]
+                    .grapheme_indices(true)
+                    .rev()
+                    .find(|(_, g)| g.starts_with(c))
+                    .map(|(i, _)| start + i)?;
+                match self {
+                    Motion::TillBack(_) => next(text, found),
+                    _ => found,
+                }
+            }
+        };
+        Some(target)
+    }
+
+    /// The range of text an operator acts on when this motion is applied
+    /// `count` times from `offset`.
+    fn range(self, text: &str, offset: usize, count: usize) -> Option<(usize, usize)> {
+        if self == Motion::Line {
+            return Some((line_start(text, offset), line_end(text, offset)));
+        }
+        let mut target = offset;
+        for _ in 0..count {
+            target = self.target(text, target)?;
+        }
+        if target < offset {
+            Some((target, offset))
+        } else if target == offset && matches!(self, Motion::Till(_) | Motion::TillBack(_)) {
+            // Till an adjacent character stays put, leaving nothing to act on.
+            Some((offset, offset))
+        } else if self.inclusive() {
+            Some((offset, next(text, target)))
+        } else {
+            Some((offset, target))
+        }
+    }
+}
+
+/// Reads a count typed before a command. A leading `0` is the motion to the
+/// start of the line rather than a count.
+fn parse_count(keys: &[char], index: &mut usize) -> Option<usize> {
+    let digits: String = keys[*index..]
+        .iter()
+        .enumerate()
+        .take_while(|&(i, c)| c.is_ascii_digit() && (i > 0 || *c != '0'))
+        .map(|(_, c)| c)
+        .collect();
+    *index += digits.len();
+    digits.parse().ok()
+}
+
+fn parse_motion(keys: &[char], index: &mut usize) -> Parsed<Motion> {
+    let c = match keys.get(*index) {
+        Some(&c) => c,
+        None => return Parsed::Incomplete,
+    };
+    *index += 1;
+    let motion = match c {
+        'h' => Motion::Left,
+        'l' | ' ' => Motion::Right,
+        'w' => Motion::WordForward(false),
+        'W' => Motion::WordForward(true),
+        'b' => Motion::WordBackward(false),
+        'B' => Motion::WordBackward(true),
+        'e' => Motion::WordEnd(false),
+        'E' => Motion::WordEnd(true),
+        '0' => Motion::LineStart,
+        '^' => Motion::FirstNonBlank,
+        '$' => Motion::LineEnd,
+        'f' | 't' | 'F' | 'T' => {
+            let target = match keys.get(*index) {
+                Some(&target) => target,
+                None => return Parsed::Incomplete,
+            };
+            *index += 1;
+            match c {
+                'f' => Motion::Find(target),
+                't' => Motion::Till(target),
+                'F' => Motion::FindBack(target),
+                _ => Motion::TillBack(target),
+            }
+        }
+        _ => return Parsed::Invalid,
+    };
+    Parsed::Done(motion)
+}
+
+/// Parses a normal mode command, made of an optional count followed by a
+/// motion, an operator and its motion, or another command.
+fn parse_command(keys: &[char]) -> Parsed<(usize, Action)> {
+    let mut index = 0;
+    let count = parse_count(keys, &mut index);
+    let c = match keys.get(index) {
+        Some(&c) => c,
+        None => return Parsed::Incomplete,
+    };
+
+    let shorthand = |operator, motion| Parsed::Done(Action::Operate(operator, motion));
+    let action = match c {
+        'd' | 'c' | 'y' => {
+            let operator = match c {
+                'd' => Operator::Delete,
+                'c' => Operator::Change,
+                _ => Operator::Yank,
+            };
+            index += 1;
+            let motion_count = parse_count(keys, &mut index);
+            let motion = if keys.get(index) == Some(&c) {
+                Parsed::Done(Motion::Line)
+            } else {
+                parse_motion(keys, &mut index)
+            };
+            let count = count.unwrap_or(1) * motion_count.unwrap_or(1);
+            return match motion {
+                Parsed::Done(motion) => Parsed::Done((count, Action::Operate(operator, motion))),
+                Parsed::Incomplete => Parsed::Incomplete,
+                Parsed::Invalid => Parsed::Invalid,
+            };
+        }
+        'x' => shorthand(Operator::Delete, Motion::Right),
+        'X' => shorthand(Operator::Delete, Motion::Left),
+        'D' => shorthand(Operator::Delete, Motion::LineEnd),
+        's' => shorthand(Operator::Change, Motion::Right),
+        'S' => shorthand(Operator::Change, Motion::Line),
+        'C' => shorthand(Operator::Change, Motion::LineEnd),
+        'Y' => shorthand(Operator::Yank, Motion::Line),
+        'i' => Parsed::Done(Action::Insert(Insert::Cursor)),
+        'a' => Parsed::Done(Action::Insert(Insert::After)),
+        'I' => Parsed::Done(Action::Insert(Insert::LineStart)),
+        'A' => Parsed::Done(Action::Insert(Insert::LineEnd)),
+        'r' => match keys.get(index + 1) {
+            Some(&c) => Parsed::Done(Action::Replace(c)),
+            None => Parsed::Incomplete,
+        },
+        'p' => Parsed::Done(Action::Put { before: false }),
+        'P' => Parsed::Done(Action::Put { before: true }),
+        'u' => Parsed::Done(Action::Undo),
+        '.' => Parsed::Done(Action::Repeat),
+        _ => match parse_motion(keys, &mut index) {
+            Parsed::Done(motion) => Parsed::Done(Action::Move(motion)),
+            Parsed::Incomplete => Parsed::Incomplete,
+            Parsed::Invalid => Parsed::Invalid,
+        },
+    };
+
+    match action {
+        Parsed::Done(action) => Parsed::Done((count.unwrap_or(1), action)),
+        Parsed::Incomplete => Parsed::Incomplete,
+        Parsed::Invalid => Parsed::Invalid,
+    }
+}
+
+/// Vi editing for a `LineBuffer`. Lines start in insert mode, where keys are
+/// passed on to be handled as usual, until Escape switches to normal mode
+/// for motions and operators.
+#[derive(Clone, Debug)]
+pub struct Vi {
+    mode: Mode,
+    /// Keys of the normal mode command being typed.
+    pending: Vec<char>,
+    /// Keys of the last change, replayed by `.`.
+    last_change: Vec<Key>,
+    /// Keys of a change that entered insert mode, recorded until Escape.
+    recording: Option<Vec<Key>>,
+    replaying: bool,
+}
+
+impl Default for Vi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vi {
+    pub fn new() -> Vi {
+        Vi {
+            mode: Mode::Insert,
+            pending: Vec::new(),
+            last_change: Vec::new(),
+            recording: None,
+            replaying: false,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Starts editing a new line, in insert mode. The last change is kept
+    /// so that `.` repeats it on the new line.
+    pub fn reset(&mut self) {
+        self.mode = Mode::Insert;
+        self.pending.clear();
+        self.recording = None;
+    }
+
+    /// Keeps the cursor on a grapheme in normal mode, rather than past the
+    /// end of the line.
+    fn clamp(buffer: &mut LineBuffer) {
+        let text = buffer.text();
+        let cursor = buffer.cursor();
+        if cursor == line_end(text, cursor) && cursor > line_start(text, cursor) {
+            buffer.set_cursor(previous(text, cursor));
+        }
+    }
+
+    pub fn handle(
+        &mut self,
+        key: Key,
+        buffer: &mut LineBuffer,
+        kill_ring: &mut KillRing,
+    ) -> Outcome {
+        match self.mode {
+            Mode::Insert => self.handle_insert(key, buffer),
+            Mode::Normal => self.handle_normal(key, buffer, kill_ring),
+        }
+    }
+
+    fn handle_insert(&mut self, key: Key, buffer: &mut LineBuffer) -> Outcome {
+        if let Some(recording) = self.recording.as_mut() {
+            recording.push(key);
+        }
+        match key {
+            Key::Esc => {
+                self.mode = Mode::Normal;
+                if let Some(recording) = self.recording.take() {
+                    if !self.replaying {
+                        self.last_change = recording;
+                    }
+                }
+                buffer.move_left();
+                Outcome::Moved
+            }
+            // Replayed keys are applied here, while typed keys go through the
+            // usual handling so that completion keeps working.
+            Key::Char(c) if self.replaying => {
+                buffer.insert(c);
+                Outcome::Edited
+            }
+            Key::Backspace if self.replaying => {
+                buffer.backspace();
+                Outcome::Edited
+            }
+            key => Outcome::Pass(key),
+        }
+    }
+
+    fn handle_normal(
+        &mut self,
+        key: Key,
+        buffer: &mut LineBuffer,
+        kill_ring: &mut KillRing,
+    ) -> Outcome {
+        let c = match key {
+            Key::Char('\n') | Key::Char('\t') => return Outcome::Pass(key),
+            Key::Char(c) => c,
+            Key::Backspace => 'h',
+            Key::Delete => 'x',
+            Key::Esc => {
+                self.pending.clear();
+                return Outcome::Pass(key);
+            }
+            key => return Outcome::Pass(key),
+        };
+        if self.pending.is_empty() {
+            match c {
+                'k' => return Outcome::Pass(Key::Up),
+                'j' => return Outcome::Pass(Key::Down),
+                _ => {}
+            }
+        }
+
+        self.pending.push(c);
+        let (count, action) = match parse_command(&self.pending) {
+            Parsed::Incomplete => return Outcome::Pending,
+            Parsed::Invalid => {
+                self.pending.clear();
+                return Outcome::Pending;
+            }
+            Parsed::Done(command) => command,
+        };
+        let keys: Vec<Key> = self.pending.drain(..).map(Key::Char).collect();
+
+        let changes = !matches!(
+            action,
+            Action::Move(_) | Action::Undo | Action::Repeat | Action::Operate(Operator::Yank, _)
+        );
+        if changes && !self.replaying {
+            self.last_change = keys.clone();
+        }
+
+        let outcome = self.execute(count, action, buffer, kill_ring);
+        if self.mode == Mode::Insert && !self.replaying {
+            self.recording = Some(keys);
+        }
+        if self.mode == Mode::Normal {
+            Vi::clamp(buffer);
+        }
+        outcome
+    }
+
+    fn execute(
+        &mut self,
+        count: usize,
+        action: Action,
+        buffer: &mut LineBuffer,
+        kill_ring: &mut KillRing,
+    ) -> Outcome {
+        let text = buffer.text();
+        let cursor = buffer.cursor();
+        match action {
+            Action::Move(motion) => {
+                let mut target = cursor;
+                for _ in 0..count {
+                    match motion.target(text, target) {
+                        Some(next) => target = next,
+                        None => break,
+                    }
+                }
+                buffer.set_cursor(target);
+                Outcome::Moved
+            }
+            Action::Operate(operator, motion) => {
+                // `cw` changes to the end of the word, like `ce`, keeping the
+                // whitespace after it.
+                let motion = match (operator, motion) {
+                    (Operator::Change, Motion::WordForward(big))
+                        if class(grapheme_at(text, cursor), big) != 0 =>
+                    {
+                        let next = next(text, cursor);
+                        if next < text.len()
+                            && class(grapheme_at(text, next), big)
+                                == class(grapheme_at(text, cursor), big)
+                        {
+                            Motion::WordEnd(big)
+                        } else {
+                            Motion::Right
+                        }
+                    }
+                    (_, motion) => motion,
+                };
+                let (start, end) = match motion.range(text, cursor, count) {
+                    Some(range) => range,
+                    None => return Outcome::Pending,
+                };
+                match operator {
+                    Operator::Yank => {
+                        kill_ring.push(&text[start..end]);
+                        buffer.set_cursor(start);
+                        Outcome::Moved
+                    }
+                    Operator::Delete | Operator::Change => {
+                        kill_ring.push(buffer.remove(start, end));
+                        if operator == Operator::Change {
+                            self.mode = Mode::Insert;
+                        }
+                        Outcome::Edited
+                    }
+                }
+            }
+            Action::Insert(at) => {
+                let target = match at {
+                    Insert::Cursor => cursor,
+                    Insert::After => Motion::Right.target(text, cursor).unwrap_or(cursor),
+                    Insert::LineStart => Motion::FirstNonBlank.target(text, cursor).unwrap_or(0),
+                    Insert::LineEnd => line_end(text, cursor),
+                };
+                buffer.set_cursor(target);
+                self.mode = Mode::Insert;
+                Outcome::Moved
+            }
+            Action::Replace(c) => {
+                let mut end = cursor;
+                for _ in 0..count {
+                    match Motion::Right.target(text, end) {
+                        Some(next) => end = next,
+                        None => return Outcome::Pending,
+                    }
+                }
+                buffer.remove(cursor, end);
+                buffer.insert_str(&c.to_string().repeat(count));
+                buffer.move_left();
+                Outcome::Edited
+            }
+            Action::Put { before } => {
+                let yanked = match kill_ring.yank() {
+                    Some(yanked) => yanked.repeat(count),
+                    None => return Outcome::Pending,
+                };
+                if !before {
+                    buffer.move_right();
+                }
+                buffer.insert_str(&yanked);
+                buffer.move_left();
+                Outcome::Edited
+            }
+            Action::Undo => Outcome::Undo,
+            Action::Repeat => {
+                let keys = self.last_change.clone();
+                self.replaying = true;
+                for key in keys {
+                    self.handle(key, buffer, kill_ring);
+                }
+                self.replaying = false;
+                Outcome::Edited
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `keys` to `vi`, inserting the characters it passes on the way
+    /// the terminal does in insert mode.
+    fn type_keys(vi: &mut Vi, buffer: &mut LineBuffer, kill_ring: &mut KillRing, keys: &str) {
+        for c in keys.chars() {
+            let key = if c == '\x1b' { Key::Esc } else { Key::Char(c) };
+            if let Outcome::Pass(Key::Char(c)) = vi.handle(key, buffer, kill_ring) {
+                buffer.insert(c);
+            }
+        }
+    }
+
+    /// The line left by typing `keys` in normal mode on `text`, with the
+    /// cursor at its start.
+    fn edit(text: &str, keys: &str) -> String {
+        let mut vi = Vi::new();
+        let mut buffer = LineBuffer::new();
+        let mut kill_ring = KillRing::new();
+        buffer.set(text);
+        type_keys(&mut vi, &mut buffer, &mut kill_ring, "\x1b0");
+        type_keys(&mut vi, &mut buffer, &mut kill_ring, keys);
+        buffer.text().to_string()
+    }
+
+    #[test]
+    fn find_and_till_targets() {
+        let text = "a(b)c)";
+        assert_eq!(Motion::Find(')').target(text, 0), Some(3));
+        assert_eq!(Motion::Till(')').target(text, 0), Some(2));
+        assert_eq!(Motion::Till(')').target(text, 2), Some(2));
+        assert_eq!(Motion::FindBack('(').target(text, 4), Some(1));
+        assert_eq!(Motion::TillBack('(').target(text, 4), Some(2));
+        assert_eq!(Motion::Find('x').target(text, 0), None);
+    }
+
+    #[test]
+    fn word_and_line_targets() {
+        let text = "foo.bar baz";
+        assert_eq!(Motion::WordForward(false).target(text, 0), Some(3));
+        assert_eq!(Motion::WordForward(true).target(text, 0), Some(8));
+        assert_eq!(Motion::WordEnd(false).target(text, 0), Some(2));
+        assert_eq!(Motion::WordBackward(false).target(text, 8), Some(4));
+        assert_eq!(Motion::LineEnd.target(text, 0), Some(10));
+        assert_eq!(Motion::Left.target(text, 0), None);
+    }
+
+    #[test]
+    fn operator_ranges() {
+        let text = "a)bc)";
+        assert_eq!(Motion::Till(')').range(text, 0, 1), Some((0, 0)));
+        assert_eq!(Motion::Till(')').range(text, 2, 1), Some((2, 4)));
+        assert_eq!(Motion::TillBack(')').range(text, 2, 1), Some((2, 2)));
+        assert_eq!(Motion::Find(')').range(text, 2, 1), Some((2, 5)));
+        assert_eq!(
+            Motion::WordForward(false).range("ab cd", 0, 2),
+            Some((0, 5))
+        );
+        assert_eq!(Motion::Line.range("ab cd", 3, 1), Some((0, 5)));
+    }
+
+    #[test]
+    fn delete_till_adjacent_character_deletes_nothing() {
+        assert_eq!(edit("a)bc", "dt)"), "a)bc");
+        assert_eq!(edit("ab)c", "dt)"), ")c");
+        assert_eq!(edit("ab)c", "$dT)"), "ab)c");
+    }
+
+    #[test]
+    fn dot_repeats_the_last_change() {
+        assert_eq!(edit("one two three", "dw."), "three");
+        assert_eq!(edit("one two three", "cwX\x1bw."), "X X three");
+        assert_eq!(edit("abcd", "x.."), "d");
+    }
+}
@@ -0,0 +1,113 @@
+use rust_prompt::arguments::{self, ArgumentResult, PromptArgs, ValidationError};
+
+#[derive(Debug, PartialEq, PromptArgs)]
+struct PingArgs {
+    /// Host to ping
+    #[prompt(positional)]
+    host: String,
+    /// More hosts
+    #[prompt(positional)]
+    also: Vec<String>,
+    #[prompt(short = "c", default = "4", help = "Number of packets")]
+    count: u32,
+    #[prompt(name = "wait")]
+    interval: Option<f32>,
+    #[prompt(short = "v")]
+    verbose: bool,
+    tag: Vec<String>,
+}
+
+fn parse(line: &str) -> Result<PingArgs, ValidationError> {
+    let flags = PingArgs::flags();
+    match arguments::parse_arguments_with_flags(line, &flags).unwrap() {
+        ArgumentResult::Command(_, values, args) => {
+            let args =
+                arguments::validate_arguments(&flags, &PingArgs::positionals(), values, args)?;
+            PingArgs::from_arguments(&args)
+        }
+        ArgumentResult::Special(..) => panic!("not a command: {}", line),
+    }
+}
+
+#[test]
+fn declares_flags_and_positionals() {
+    let flags = PingArgs::flags();
+    let names: Vec<&str> = flags.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, vec!["count", "wait", "verbose", "tag"]);
+
+    let count = &flags[0];
+    assert_eq!(count.short.as_deref(), Some("c"));
+    assert_eq!(count.default.as_deref(), Some("4"));
+    assert_eq!(count.help, "Number of packets");
+    assert!(!count.required);
+    assert_eq!(flags[1].kind, arguments::ArgumentType::String);
+    assert!(!flags[1].required);
+    assert_eq!(flags[2].kind, arguments::ArgumentType::Bool);
+    assert!(flags[3].multiple);
+
+    let positionals = PingArgs::positionals();
+    let names: Vec<&str> = positionals.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(names, vec!["host", "also"]);
+    assert!(positionals[0].required);
+    assert_eq!(positionals[0].help, "Host to ping");
+    assert!(!positionals[1].required);
+    assert!(positionals[1].multiple);
+}
+
+#[test]
+fn fills_in_defaults() {
+    assert_eq!(
+        parse("ping example.com").unwrap(),
+        PingArgs {
+            host: "example.com".to_string(),
+            also: Vec::new(),
+            count: 4,
+            interval: None,
+            verbose: false,
+            tag: Vec::new(),
+        }
+    );
+}
+
+#[test]
+fn reads_every_kind_of_field() {
+    assert_eq!(
+        parse("ping a b c -c 2 --wait 0.5 -v --tag x --tag y").unwrap(),
+        PingArgs {
+            host: "a".to_string(),
+            also: vec!["b".to_string(), "c".to_string()],
+            count: 2,
+            interval: Some(0.5),
+            verbose: true,
+            tag: vec!["x".to_string(), "y".to_string()],
+        }
+    );
+    assert_eq!(parse("ping a --count 7").unwrap().count, 7);
+    assert_eq!(parse("ping a --tag x").unwrap().tag, vec!["x"]);
+}
+
+#[test]
+fn reports_invalid_input() {
+    assert_eq!(
+        parse("ping"),
+        Err(ValidationError::MissingPositional(
+            "host".to_string(),
+            "Host to ping".to_string()
+        ))
+    );
+    assert_eq!(
+        parse("ping a -c many"),
+        Err(ValidationError::InvalidValue(
+            "count".to_string(),
+            "many".to_string()
+        ))
+    );
+    assert_eq!(
+        parse("ping a --interval 1"),
+        Err(ValidationError::UnknownFlag("interval".to_string()))
+    );
+    assert_eq!(
+        parse("ping a -c 1 -c 2"),
+        Err(ValidationError::Repeated("count".to_string()))
+    );
+}